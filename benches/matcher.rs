@@ -0,0 +1,112 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use ozb::matcher::KeywordMatcher;
+use std::hint::black_box;
+
+const WORDS: [&str; 16] = [
+    "ssd",
+    "nvme",
+    "headphones",
+    "lego",
+    "coffee",
+    "airpods",
+    "switch",
+    "monitor",
+    "laptop",
+    "keyboard",
+    "vacuum",
+    "kindle",
+    "router",
+    "charger",
+    "blender",
+    "camera",
+];
+
+fn keywords(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| match i % 3 {
+            0 => WORDS[i % WORDS.len()].to_owned(),
+            1 => format!("{} {}", WORDS[i % WORDS.len()], i),
+            _ => format!("brand{i}"),
+        })
+        .collect()
+}
+
+fn posts() -> Vec<(String, String)> {
+    (0..10)
+        .map(|i| {
+            (
+                format!(
+                    "Samsung 990 Pro 2TB NVMe SSD ${} (20% off) @ Amazon AU brand{}",
+                    100 + i,
+                    i * 7
+                ),
+                "Great price on this drive, it was cheaper last week but still a good deal. \
+                 Comes with a heatsink and free shipping with Prime, see the links below for \
+                 other retailers and the price history."
+                    .repeat(4),
+            )
+        })
+        .collect()
+}
+
+/// The trigger's previous approach, every keyword checked against every post
+fn naive(keywords: &[String], posts: &[(String, String)]) -> usize {
+    let keywords = keywords
+        .iter()
+        .map(|k| k.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    let mut matched = 0;
+    for (title, description) in posts {
+        let title = title.to_ascii_lowercase();
+        let description = description.to_ascii_lowercase();
+        for keyword in &keywords {
+            if title.contains(keyword) || description.contains(keyword) {
+                matched += 1;
+            }
+        }
+    }
+
+    matched
+}
+
+fn compiled(matcher: &KeywordMatcher, posts: &[(String, String)]) -> usize {
+    posts
+        .iter()
+        .map(|(title, description)| matcher.matches(&[title, description]).len())
+        .sum()
+}
+
+fn bench_matchers(c: &mut Criterion) {
+    let posts = posts();
+    let mut group = c.benchmark_group("match 10 posts");
+
+    for count in [100, 1_000, 10_000] {
+        let keywords = keywords(count);
+        let matcher = KeywordMatcher::new(keywords.iter().map(String::as_str)).unwrap();
+        assert_eq!(naive(&keywords, &posts), compiled(&matcher, &posts));
+
+        group.bench_with_input(
+            BenchmarkId::new("naive", count),
+            &keywords,
+            |b, keywords| b.iter(|| naive(black_box(keywords), black_box(&posts))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("aho-corasick", count),
+            &matcher,
+            |b, matcher| b.iter(|| compiled(black_box(matcher), black_box(&posts))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("build", count),
+            &keywords,
+            |b, keywords| {
+                b.iter(|| KeywordMatcher::new(keywords.iter().map(String::as_str)).unwrap())
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_matchers);
+criterion_main!(benches);
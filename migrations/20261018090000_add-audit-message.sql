@@ -0,0 +1,2 @@
+ALTER TABLE audit ADD COLUMN channel_id TEXT;
+ALTER TABLE audit ADD COLUMN message_id TEXT;
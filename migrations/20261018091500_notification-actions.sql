@@ -0,0 +1,9 @@
+ALTER TABLE registered_keywords ADD COLUMN snoozed_until TIMESTAMP WITHOUT TIME ZONE;
+
+CREATE TABLE muted_merchants (
+	id SERIAL PRIMARY KEY,
+	discord_user_id INTEGER REFERENCES discord_users(id) ON DELETE CASCADE NOT NULL,
+	merchant TEXT NOT NULL,
+	created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now(),
+	UNIQUE (discord_user_id, merchant)
+);
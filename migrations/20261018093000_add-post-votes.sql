@@ -0,0 +1,2 @@
+ALTER TABLE ozbargain_posts ADD COLUMN votes_positive INTEGER;
+ALTER TABLE ozbargain_posts ADD COLUMN votes_negative INTEGER;
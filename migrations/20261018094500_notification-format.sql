@@ -0,0 +1,2 @@
+ALTER TABLE registered_keywords ADD COLUMN notification_format TEXT NOT NULL DEFAULT 'standard';
+ALTER TABLE registered_keywords ADD COLUMN notification_template TEXT;
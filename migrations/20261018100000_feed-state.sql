@@ -0,0 +1,6 @@
+CREATE TABLE feed_state (
+	url TEXT PRIMARY KEY,
+	etag TEXT,
+	last_modified TEXT,
+	updated_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now()
+);
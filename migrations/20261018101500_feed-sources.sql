@@ -0,0 +1,20 @@
+CREATE TABLE feed_sources (
+	id SERIAL PRIMARY KEY,
+	name TEXT UNIQUE NOT NULL,
+	url TEXT NOT NULL,
+	poll_interval_seconds INTEGER NOT NULL DEFAULT 60,
+	enabled BOOLEAN NOT NULL DEFAULT true,
+	created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now()
+);
+
+-- category feeds can be added the same way, e.g. https://www.ozbargain.com.au/cat/computing/feed
+INSERT INTO feed_sources (name, url, poll_interval_seconds) VALUES ('deals', 'https://www.ozbargain.com.au/deals/feed', 60);
+INSERT INTO feed_sources (name, url, poll_interval_seconds) VALUES ('popular', 'https://www.ozbargain.com.au/deals/popular/feed', 300);
+INSERT INTO feed_sources (name, url, poll_interval_seconds) VALUES ('freebies', 'https://www.ozbargain.com.au/freebies/feed', 300);
+INSERT INTO feed_sources (name, url, poll_interval_seconds) VALUES ('competitions', 'https://www.ozbargain.com.au/competition/feed', 900);
+INSERT INTO feed_sources (name, url, poll_interval_seconds) VALUES ('forum', 'https://www.ozbargain.com.au/forum/feed', 300);
+
+ALTER TABLE ozbargain_posts ADD COLUMN feed_source_id INTEGER REFERENCES feed_sources(id);
+UPDATE ozbargain_posts SET feed_source_id = (SELECT id FROM feed_sources WHERE name = 'deals');
+
+ALTER TABLE registered_keywords ADD COLUMN feed_sources TEXT[] NOT NULL DEFAULT '{deals}';
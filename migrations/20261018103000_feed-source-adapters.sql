@@ -0,0 +1,6 @@
+-- adapter is one of ozbargain, rss or atom, the *_field columns override where
+-- the generic adapters read guid, categories and thumbnail from
+ALTER TABLE feed_sources ADD COLUMN adapter TEXT NOT NULL DEFAULT 'ozbargain';
+ALTER TABLE feed_sources ADD COLUMN guid_field TEXT;
+ALTER TABLE feed_sources ADD COLUMN categories_field TEXT;
+ALTER TABLE feed_sources ADD COLUMN thumbnail_field TEXT;
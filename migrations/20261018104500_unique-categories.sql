@@ -0,0 +1,2 @@
+ALTER TABLE categories ADD CONSTRAINT categories_name_key UNIQUE (name);
+ALTER TABLE categories ALTER COLUMN emoji SET DEFAULT '🏷️';
@@ -0,0 +1,16 @@
+ALTER TABLE ozbargain_posts ADD COLUMN updated_at TIMESTAMP WITHOUT TIME ZONE;
+
+-- previous versions of a post, written by the finder when a known guid changes.
+-- unprocessed rows are "deal updated" events for the trigger
+CREATE TABLE ozbargain_post_revisions (
+	id SERIAL PRIMARY KEY,
+	post_id INTEGER REFERENCES ozbargain_posts(id) ON DELETE CASCADE NOT NULL,
+	title TEXT NOT NULL,
+	description TEXT NOT NULL,
+	link TEXT NOT NULL,
+	categories TEXT[] NOT NULL,
+	processed BOOLEAN NOT NULL DEFAULT false,
+	created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now()
+);
+
+CREATE INDEX ozbargain_post_revisions_unprocessed ON ozbargain_post_revisions (id) WHERE processed = false;
@@ -0,0 +1,10 @@
+-- opt-in to notifications when a previously notified deal drops in price or is re-posted
+ALTER TABLE registered_keywords ADD COLUMN renotify BOOLEAN NOT NULL DEFAULT false;
+
+-- re-posts of the same deal under a new guid are linked to the latest earlier post
+ALTER TABLE ozbargain_posts ADD COLUMN deal_url TEXT;
+ALTER TABLE ozbargain_posts ADD COLUMN product_key TEXT;
+ALTER TABLE ozbargain_posts ADD COLUMN reposted_from INTEGER REFERENCES ozbargain_posts(id) ON DELETE SET NULL;
+
+CREATE INDEX ozbargain_posts_deal_url ON ozbargain_posts (deal_url);
+CREATE INDEX ozbargain_posts_product_key ON ozbargain_posts (product_key);
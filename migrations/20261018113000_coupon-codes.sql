@@ -0,0 +1,3 @@
+ALTER TABLE ozbargain_posts ADD COLUMN coupon_codes TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX ozbargain_posts_coupon_codes ON ozbargain_posts USING GIN (coupon_codes);
@@ -0,0 +1,6 @@
+-- plain text of the html description, see ozbargain::description_text.
+-- older posts are converted by the trigger when it reads them
+ALTER TABLE ozbargain_posts ADD COLUMN description_text TEXT;
+
+CREATE INDEX ozbargain_posts_search ON ozbargain_posts
+	USING GIN (to_tsvector('english', title || ' ' || COALESCE(description_text, '')));
@@ -0,0 +1,14 @@
+-- replaces the notified flag, posts are leased by a trigger while processing so a
+-- crashed or failed batch is retried instead of lost
+ALTER TABLE ozbargain_posts ADD COLUMN processing_state TEXT NOT NULL DEFAULT 'pending'
+	CHECK (processing_state IN ('pending', 'processing', 'done', 'failed'));
+ALTER TABLE ozbargain_posts ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+-- end of the lease while processing, earliest retry when failed
+ALTER TABLE ozbargain_posts ADD COLUMN leased_until TIMESTAMP WITHOUT TIME ZONE;
+ALTER TABLE ozbargain_posts ADD COLUMN last_error TEXT;
+
+UPDATE ozbargain_posts SET processing_state = CASE WHEN notified THEN 'done' ELSE 'pending' END;
+
+ALTER TABLE ozbargain_posts DROP COLUMN notified;
+
+CREATE INDEX ozbargain_posts_unprocessed ON ozbargain_posts (id) WHERE processing_state <> 'done';
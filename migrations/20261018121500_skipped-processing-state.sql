@@ -0,0 +1,8 @@
+-- posts too old to notify when the trigger got to them
+ALTER TABLE ozbargain_posts DROP CONSTRAINT ozbargain_posts_processing_state_check;
+ALTER TABLE ozbargain_posts ADD CONSTRAINT ozbargain_posts_processing_state_check
+	CHECK (processing_state IN ('pending', 'processing', 'done', 'failed', 'skipped'));
+
+DROP INDEX ozbargain_posts_unprocessed;
+CREATE INDEX ozbargain_posts_unprocessed ON ozbargain_posts (id)
+	WHERE processing_state NOT IN ('done', 'skipped');
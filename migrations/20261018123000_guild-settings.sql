@@ -0,0 +1,12 @@
+CREATE TABLE guild_settings (
+	guild_id TEXT PRIMARY KEY,
+	-- channels /register may target, empty allows any channel
+	allowed_channel_ids TEXT[] NOT NULL DEFAULT '{}',
+	-- keywords each user may register in the guild, null is unlimited
+	keyword_quota INTEGER,
+	default_channel_id TEXT,
+	updated_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now()
+);
+
+-- guild the keyword was registered in, null for direct messages and older registrations
+ALTER TABLE registered_keywords ADD COLUMN guild_id TEXT;
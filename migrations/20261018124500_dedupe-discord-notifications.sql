@@ -0,0 +1,28 @@
+-- notification targets are shared by every registration for the same channel
+ALTER TABLE discord_notifications ADD COLUMN type TEXT NOT NULL DEFAULT 'channel';
+
+UPDATE registered_keywords AS rk SET discord_notification_id = keep.id
+FROM discord_notifications AS dn
+JOIN (
+	SELECT type, channel_id, min(id) AS id FROM discord_notifications GROUP BY type, channel_id
+) AS keep ON keep.type = dn.type AND keep.channel_id = dn.channel_id
+WHERE rk.discord_notification_id = dn.id AND dn.id <> keep.id;
+
+DELETE FROM discord_notifications AS dn
+WHERE NOT EXISTS (SELECT 1 FROM registered_keywords AS rk WHERE rk.discord_notification_id = dn.id);
+
+ALTER TABLE discord_notifications ADD CONSTRAINT discord_notifications_type_channel_id_key UNIQUE (type, channel_id);
+
+-- removes targets once their last registration is gone, however it was deleted
+CREATE FUNCTION delete_unused_discord_notification() RETURNS TRIGGER AS $$
+BEGIN
+	DELETE FROM discord_notifications AS dn
+	WHERE dn.id = OLD.discord_notification_id
+		AND NOT EXISTS (SELECT 1 FROM registered_keywords AS rk WHERE rk.discord_notification_id = dn.id);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER registered_keywords_delete_unused_discord_notification
+AFTER DELETE OR UPDATE OF discord_notification_id ON registered_keywords
+FOR EACH ROW EXECUTE FUNCTION delete_unused_discord_notification();
@@ -0,0 +1,15 @@
+-- a post can be in several feeds, e.g. deals reach the deals feed before popular,
+-- so keywords watching popular match posts that were stored from deals first
+CREATE TABLE post_feed_sources (
+	post_id INTEGER NOT NULL REFERENCES ozbargain_posts(id) ON DELETE CASCADE,
+	feed_source_id INTEGER NOT NULL REFERENCES feed_sources(id) ON DELETE CASCADE,
+	created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT now(),
+	PRIMARY KEY (post_id, feed_source_id)
+);
+
+CREATE INDEX post_feed_sources_feed_source_id ON post_feed_sources (feed_source_id);
+
+INSERT INTO post_feed_sources (post_id, feed_source_id)
+SELECT id, feed_source_id FROM ozbargain_posts WHERE feed_source_id IS NOT NULL;
+
+ALTER TABLE ozbargain_posts DROP COLUMN feed_source_id;
@@ -0,0 +1,12 @@
+-- price a deal was notified at, price drops are measured from it
+ALTER TABLE audit ADD COLUMN price DOUBLE PRECISION;
+
+-- store-wide deals link to the store's homepage, which doesn't identify a deal,
+-- so re-posts linked only by it are unlinked
+UPDATE ozbargain_posts AS op SET reposted_from = NULL
+FROM ozbargain_posts AS prev
+WHERE op.reposted_from = prev.id
+	AND op.deal_url ~* '^[a-z]+://[^/?#]+/?([?#].*)?$'
+	AND NOT COALESCE(op.product_key = prev.product_key, false);
+
+UPDATE ozbargain_posts SET deal_url = NULL WHERE deal_url ~* '^[a-z]+://[^/?#]+/?([?#].*)?$';
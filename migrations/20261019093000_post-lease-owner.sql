@@ -0,0 +1,2 @@
+-- batch holding the lease, so a trigger whose lease was taken over stops sending
+ALTER TABLE ozbargain_posts ADD COLUMN leased_by TEXT;
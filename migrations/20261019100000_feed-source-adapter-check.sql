@@ -0,0 +1,6 @@
+-- sources with an unknown adapter never polled, keep them disabled as generic rss feeds
+UPDATE feed_sources SET adapter = 'rss', enabled = false
+WHERE adapter NOT IN ('ozbargain', 'rss', 'atom');
+
+ALTER TABLE feed_sources ADD CONSTRAINT feed_sources_adapter_check
+    CHECK (adapter IN ('ozbargain', 'rss', 'atom'));
@@ -0,0 +1,13 @@
+-- guids of generic feeds are prefixed with their source id, as the finder now stores them,
+-- posts also in an OzBargain feed keep their node id
+UPDATE ozbargain_posts AS op
+SET ozbargain_id = pfs.feed_source_id || ':' || op.ozbargain_id
+FROM post_feed_sources AS pfs
+JOIN feed_sources AS fs ON fs.id = pfs.feed_source_id
+WHERE pfs.post_id = op.id
+    AND fs.adapter <> 'ozbargain'
+    AND NOT EXISTS (
+        SELECT 1 FROM post_feed_sources AS other
+        JOIN feed_sources AS other_fs ON other_fs.id = other.feed_source_id
+        WHERE other.post_id = op.id AND other_fs.adapter = 'ozbargain'
+    );
@@ -0,0 +1,2 @@
+-- /search matches codes by substring, which the array index can't serve
+DROP INDEX ozbargain_posts_coupon_codes;
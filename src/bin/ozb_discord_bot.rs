@@ -1,8 +1,14 @@
 use anyhow::Context;
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt as _};
+use ozb::{
+    keyword::normalize_keyword,
+    notification::{validate_template, NotificationAction, NotificationFormat},
+    ozbargain::{merchant, DEALS_SOURCE},
+    util::redact_connection_string,
+};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Pool, Postgres};
-use std::{error::Error, sync::Arc};
+use std::{collections::HashMap, error::Error, sync::Arc};
 use tracing::{instrument, Level};
 use tracing_subscriber::filter::Targets;
 use tracing_subscriber::layer::SubscriberExt;
@@ -10,12 +16,17 @@ use tracing_subscriber::util::SubscriberInitExt;
 use twilight_cache_inmemory::{DefaultCacheModels, InMemoryCache, ResourceType};
 use twilight_gateway::{Event, EventType, EventTypeFlags, Intents, Shard, ShardId, StreamExt};
 use twilight_http::Client as DiscordHttpClient;
-use twilight_model::channel::message::component::SelectMenuType;
+use twilight_model::channel::message::component::{Button, ButtonStyle, SelectMenuType};
 use twilight_model::channel::message::EmojiReactionType;
 use twilight_model::channel::message::{
     component::{SelectMenu, SelectMenuOption},
     MessageFlags,
 };
+use twilight_model::guild::Permissions;
+use twilight_model::id::{
+    marker::{ApplicationMarker, ChannelMarker, GuildMarker},
+    Id,
+};
 use twilight_standby::Standby;
 use twilight_util::builder::InteractionResponseDataBuilder;
 use vesper::{
@@ -31,13 +42,44 @@ use vesper::{
 struct BotContext {
     pool: Pool<Postgres>,
     standby: Standby,
+    discord_http: Arc<DiscordHttpClient>,
+    app_id: Id<ApplicationMarker>,
+    keyword_quota: i64,
 }
 
+const DEFAULT_KEYWORD_QUOTA: i64 = 50;
+/// Options a select menu can hold
+const SELECT_MENU_OPTIONS: usize = 25;
+/// Category menus on /register, a message holds 5 rows and the last has the register button
+const CATEGORY_MENUS: usize = 4;
+
 #[error_handler]
 async fn handle_interaction_error(_ctx: &SlashContext<Arc<BotContext>>, error: DefaultError) {
     tracing::error!("error handling interaction: {:?}", error);
 }
 
+#[autocomplete]
+async fn autocomplete_feed_sources(
+    ctx: AutocompleteContext<Arc<BotContext>>,
+) -> Option<InteractionResponseData> {
+    let choices = sqlx::query!("SELECT name FROM feed_sources WHERE enabled = true ORDER BY id")
+        .fetch_all(&ctx.data.pool)
+        .await
+        .ok()?
+        .into_iter()
+        .map(|item| CommandOptionChoice {
+            name: item.name.clone(),
+            name_localizations: None,
+            value: CommandOptionChoiceValue::String(item.name),
+        })
+        .collect();
+
+    Some(InteractionResponseData {
+        choices: Some(choices),
+        ..Default::default()
+    })
+}
+
 #[instrument(skip(ctx))]
 #[command("register")]
 #[description = "register a keyword...."]
@@ -45,6 +87,11 @@ async fn handle_interaction_error(_ctx: &SlashContext<Arc<BotContext>>, error: D
 async fn handle_register_keywords(
     ctx: &SlashContext<Arc<BotContext>>,
     #[description = "what u want"] keyword: String,
+    #[autocomplete(autocomplete_feed_sources)]
+    #[description = "which feed to watch, defaults to deals"]
+    source: Option<String>,
+    #[description = "where to notify, defaults to the server's default channel or this one"]
+    channel: Option<Id<ChannelMarker>>,
 ) -> DefaultCommandResult {
     let response = InteractionResponseDataBuilder::default().flags(MessageFlags::EPHEMERAL);
     ctx.interaction_client
@@ -64,85 +111,254 @@ async fn handle_register_keywords(
         .context("must have author")?
         .to_string();
 
-    let channel_id = interaction
-        .channel
+    let keyword = match normalize_keyword(&keyword) {
+        Ok(keyword) => keyword,
+        Err(e) => {
+            ctx.interaction_client
+                .update_response(&ctx.interaction.token)
+                .content(Some(&e.to_string()))
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let guild_id = interaction.guild_id.map(|id| id.to_string());
+    let guild_settings = match &guild_id {
+        Some(guild_id) => {
+            sqlx::query!("SELECT * FROM guild_settings WHERE guild_id = $1", guild_id)
+                .fetch_optional(&ctx.data.pool)
+                .await?
+        }
+        None => None,
+    };
+
+    let channel_id = match (
+        channel,
+        guild_settings
+            .as_ref()
+            .and_then(|g| g.default_channel_id.clone()),
+    ) {
+        (Some(channel), _) => channel.to_string(),
+        (None, Some(default_channel_id)) => default_channel_id,
+        (None, None) => interaction
+            .channel
+            .as_ref()
+            .context("must be from a channel")?
+            .id
+            .to_string(),
+    };
+
+    let current_channel_id = interaction.channel.as_ref().map(|c| c.id.to_string());
+    if current_channel_id.as_ref() != Some(&channel_id)
+        && !channel_in_guild(&ctx.data.discord_http, &channel_id, interaction.guild_id).await
+    {
+        ctx.interaction_client
+            .update_response(&ctx.interaction.token)
+            .content(Some("Keywords can only notify channels in this server"))
+            .await?;
+
+        return Ok(());
+    }
+
+    if let Some(guild_settings) = &guild_settings {
+        let allowed = &guild_settings.allowed_channel_ids;
+        if !allowed.is_empty() && !allowed.contains(&channel_id) {
+            ctx.interaction_client
+                .update_response(&ctx.interaction.token)
+                .content(Some(&format!(
+                    "Keywords can't notify <#{channel_id}>, pick one of: {}",
+                    channel_list(allowed)
+                )))
+                .await?;
+
+            return Ok(());
+        }
+    }
+
+    let registered = sqlx::query!(
+        r#"SELECT count(*) AS "total!",
+                count(*) FILTER (WHERE rk.guild_id = $2) AS "in_guild!",
+                bool_or(rk.keyword = $3 AND dn.channel_id = $4) AS duplicate
+            FROM registered_keywords AS rk
+            JOIN discord_users AS du ON rk.discord_user_id = du.id
+            JOIN discord_notifications AS dn ON rk.discord_notification_id = dn.id
+            WHERE du.discord_id = $1"#,
+        discord_id,
+        guild_id,
+        keyword,
+        channel_id
+    )
+    .fetch_one(&ctx.data.pool)
+    .await?;
+
+    let guild_quota = guild_settings
         .as_ref()
-        .context("must be from a channel")?
-        .id
-        .to_string();
+        .and_then(|g| g.keyword_quota)
+        .map(i64::from);
+    let error = if registered.duplicate.unwrap_or(false) {
+        Some(format!(
+            "\"{keyword}\" is already registered for <#{channel_id}>"
+        ))
+    } else if registered.total >= ctx.data.keyword_quota {
+        Some(format!(
+            "You can register at most {} keywords, unregister one first",
+            ctx.data.keyword_quota
+        ))
+    } else if let Some(quota) = guild_quota.filter(|quota| registered.in_guild >= *quota) {
+        Some(format!(
+            "You can register at most {quota} keywords in this server, unregister one first"
+        ))
+    } else {
+        None
+    };
+
+    if let Some(error) = error {
+        ctx.interaction_client
+            .update_response(&ctx.interaction.token)
+            .content(Some(&error))
+            .await?;
+
+        return Ok(());
+    }
+
+    let source = source.unwrap_or_else(|| DEALS_SOURCE.to_owned());
+    let source_exists = sqlx::query!("SELECT id FROM feed_sources WHERE name = $1", source)
+        .fetch_optional(&ctx.data.pool)
+        .await?
+        .is_some();
+
+    if !source_exists {
+        ctx.interaction_client
+            .update_response(&ctx.interaction.token)
+            .content(Some(&format!("Unknown feed \"{}\"", source)))
+            .await?;
+
+        return Ok(());
+    }
 
     let mut queried_categories = sqlx::query!("SELECT id, name, emoji FROM categories")
         .fetch_all(&ctx.data.pool)
         .await?;
 
     queried_categories.sort_by_key(|r| r.name.to_owned());
+    // select menus hold 25 options, so categories are spread over a menu per row
+    let pages = queried_categories
+        .chunks(SELECT_MENU_OPTIONS)
+        .take(CATEGORY_MENUS)
+        .collect::<Vec<_>>();
+    let hidden = queried_categories.len() - pages.iter().map(|p| p.len()).sum::<usize>();
+    if hidden > 0 {
+        tracing::warn!("{hidden} categories don't fit in the /register menus");
+    }
 
-    let option_count = queried_categories.len();
     let uuid = uuid::Uuid::new_v4().as_hyphenated().to_string();
-    let modal = SelectMenu {
-        custom_id: uuid.clone(),
-        channel_types: None,
-        default_values: None,
-        kind: SelectMenuType::Text,
-        disabled: false,
-        max_values: Some(option_count as u8),
-        min_values: Some(1),
-        options: Some(
-            queried_categories
-                .iter()
-                .map(|c| SelectMenuOption {
-                    default: false,
-                    description: None,
-                    emoji: Some(EmojiReactionType::Unicode {
-                        name: c.emoji.to_owned(),
-                    }),
-                    label: c.name.to_owned(),
-                    value: c.id.to_string(),
-                })
-                .collect(),
-        ),
-        placeholder: Some("Select categories".to_owned()),
-    };
+    let done_id = format!("{uuid}:done");
+    let mut components = pages
+        .iter()
+        .enumerate()
+        .map(|(page, categories)| {
+            let menu = SelectMenu {
+                custom_id: format!("{uuid}:{page}"),
+                channel_types: None,
+                default_values: None,
+                kind: SelectMenuType::Text,
+                disabled: false,
+                max_values: Some(categories.len() as u8),
+                min_values: Some(0),
+                options: Some(
+                    categories
+                        .iter()
+                        .map(|c| SelectMenuOption {
+                            default: false,
+                            description: None,
+                            emoji: Some(EmojiReactionType::Unicode {
+                                name: c.emoji.to_owned(),
+                            }),
+                            label: c.name.to_owned(),
+                            value: c.id.to_string(),
+                        })
+                        .collect(),
+                ),
+                placeholder: Some(format!("Select categories ({}/{})", page + 1, pages.len())),
+            };
 
-    let action_row = ActionRow {
-        components: [modal.into()].into(),
-    };
+            Component::ActionRow(ActionRow {
+                components: [menu.into()].into(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    components.push(Component::ActionRow(ActionRow {
+        components: [Component::Button(Button {
+            custom_id: Some(done_id.clone()),
+            disabled: false,
+            emoji: None,
+            label: Some("Register".to_owned()),
+            style: ButtonStyle::Primary,
+            url: None,
+            sku_id: None,
+        })]
+        .into(),
+    }));
+
+    let mut content =
+        "Select categories and press Register, with none selected all categories match".to_owned();
+    if hidden > 0 {
+        content.push_str(&format!(
+            " ({hidden} categories didn't fit and can't be picked)"
+        ));
+    }
 
     let component_message = ctx
         .interaction_client
         .update_response(&ctx.interaction.token)
-        .components(Some(&[Component::ActionRow(action_row)]))
+        .content(Some(&content))
+        .components(Some(&components))
         .await?
         .model()
         .await?;
 
-    let wait_for_selection = ctx
-        .data
-        .standby
-        .wait_for_component(component_message.id, move |i: &Interaction| {
-            i.data.clone().is_some_and(|data| match data {
-                InteractionData::MessageComponent(m) => m.custom_id == uuid,
-                _ => false,
-            })
-        })
-        .await?;
+    let prefix = format!("{uuid}:");
+    let mut selections =
+        ctx.data
+            .standby
+            .wait_for_component_stream(component_message.id, move |i: &Interaction| {
+                i.data.as_ref().is_some_and(|data| match data {
+                    InteractionData::MessageComponent(m) => m.custom_id.starts_with(&prefix),
+                    _ => false,
+                })
+            });
 
-    ctx.interaction_client
-        .create_response(
-            wait_for_selection.id,
-            &wait_for_selection.token,
-            &InteractionResponse {
-                kind: InteractionResponseType::DeferredUpdateMessage,
-                data: None,
-            },
-        )
-        .await?;
+    // each menu sends its whole selection when it changes, the button finishes
+    let mut selected = HashMap::<String, Vec<String>>::new();
+    loop {
+        let interaction = selections
+            .next()
+            .await
+            .context("stopped waiting for categories")?;
 
-    let values = match wait_for_selection.data.unwrap() {
-        InteractionData::MessageComponent(m) => m.values,
-        _ => return Err(anyhow::Error::msg("this should not happen").into()),
-    };
+        ctx.interaction_client
+            .create_response(
+                interaction.id,
+                &interaction.token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::DeferredUpdateMessage,
+                    data: None,
+                },
+            )
+            .await?;
 
+        match interaction.data {
+            Some(InteractionData::MessageComponent(m)) if m.custom_id == done_id => break,
+            Some(InteractionData::MessageComponent(m)) => {
+                selected.insert(m.custom_id, m.values);
+            }
+            _ => return Err(anyhow::Error::msg("this should not happen").into()),
+        }
+    }
+
+    let values = selected.into_values().flatten().collect::<Vec<_>>();
     let categories = if values.iter().any(|v| *v == "1") {
         vec![1]
     } else {
@@ -162,6 +378,8 @@ async fn handle_register_keywords(
         })
         .collect::<Vec<_>>();
 
+    let feed_sources = vec![source.clone()];
+
     let mut transaction = ctx.data.pool.begin().await?;
     sqlx::query!(
         "INSERT INTO discord_users(discord_id) VALUES ($1) ON CONFLICT DO NOTHING",
@@ -178,30 +396,39 @@ async fn handle_register_keywords(
     .await?;
 
     let discord_notification_id = sqlx::query!(
-        "INSERT INTO discord_notifications (channel_id) VALUES ($1) returning id",
+        r#"INSERT INTO discord_notifications (channel_id) VALUES ($1)
+            ON CONFLICT (type, channel_id) DO UPDATE SET channel_id = EXCLUDED.channel_id
+            RETURNING id"#,
         channel_id
     )
     .fetch_one(&mut *transaction)
     .await?;
 
     sqlx::query!(
-        "INSERT INTO registered_keywords (keyword, discord_user_id, discord_notification_id, categories) VALUES ($1, $2, $3, $4)",
+        "INSERT INTO registered_keywords (keyword, discord_user_id, discord_notification_id, categories, feed_sources, guild_id) VALUES ($1, $2, $3, $4, $5, $6)",
         keyword,
         discord_user_id.id,
         discord_notification_id.id,
-        &named_categories
+        &named_categories,
+        &feed_sources,
+        guild_id
     )
     .execute(&mut *transaction)
     .await?;
 
     transaction.commit().await?;
 
+    let category_list = if named_categories.is_empty() {
+        "all".to_owned()
+    } else {
+        named_categories.join(", ")
+    };
+
     ctx.interaction_client
         .update_response(&ctx.interaction.token)
         .content(Some(&format!(
-            "Registered \"{}\" as keyword for search with categories: {}",
-            keyword,
-            named_categories.join(", ")
+            "Registered \"{}\" as keyword for search in {} with categories: {}, notifying <#{}>",
+            keyword, source, category_list, channel_id
         )))
         .components(None)
         .await?;
@@ -280,9 +507,695 @@ async fn handle_unregister_keywords(
     Ok(())
 }
 
+#[instrument(skip(ctx))]
+#[command("history")]
+#[description = "show your previously matched deals..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_history(
+    ctx: &SlashContext<Arc<BotContext>>,
+    #[autocomplete(autocomplete_existing_keywords)]
+    #[description = "only show deals matched by this keyword"]
+    keyword: Option<String>,
+    #[description = "how many deals to show (max 25)"] count: Option<i64>,
+) -> DefaultCommandResult {
+    let response = InteractionResponseDataBuilder::default().flags(MessageFlags::EPHEMERAL);
+    ctx.interaction_client
+        .create_response(
+            ctx.interaction.id,
+            &ctx.interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: Some(response.build()),
+            },
+        )
+        .await?;
+
+    let discord_id = ctx
+        .interaction
+        .author_id()
+        .context("must have author")?
+        .to_string();
+
+    let keyword_id = keyword.map(|k| k.parse::<i32>()).transpose()?;
+    let count = count.unwrap_or(10).clamp(1, 25);
+
+    let history = sqlx::query!(
+        r#"
+        SELECT a.created_at, a.channel_id, a.message_id, rk.keyword AS "keyword?", op.title AS "title?", op.link AS "link?"
+        FROM audit AS a
+        JOIN discord_users AS du ON a.user_id = du.id
+        LEFT JOIN registered_keywords AS rk ON a.keyword_id = rk.id
+        LEFT JOIN ozbargain_posts AS op ON a.post_id = op.id
+        WHERE du.discord_id = $1 AND ($2::INTEGER IS NULL OR a.keyword_id = $2)
+        ORDER BY a.created_at DESC
+        LIMIT $3
+    "#,
+        discord_id,
+        keyword_id,
+        count
+    )
+    .fetch_all(&ctx.data.pool)
+    .await?;
+
+    // pings may be in another server or a dm, so links use the guild of the channel pinged
+    let mut channel_guilds = HashMap::<String, Option<String>>::new();
+    let mut content = String::new();
+    for entry in history {
+        let timestamp = entry
+            .created_at
+            .map(|t| format!("<t:{}:R>", t.and_utc().timestamp()))
+            .unwrap_or_else(|| "unknown".to_owned());
+        let title = entry.title.unwrap_or_else(|| "deleted post".to_owned());
+        let keyword = entry
+            .keyword
+            .unwrap_or_else(|| "removed keyword".to_owned());
+
+        let mut line = match entry.link {
+            Some(link) => format!("{timestamp} [{title}](<{link}>) - \"{keyword}\""),
+            None => format!("{timestamp} {title} - \"{keyword}\""),
+        };
+
+        if let (Some(channel_id), Some(message_id)) = (entry.channel_id, entry.message_id) {
+            let guild_id = match channel_guilds.get(&channel_id) {
+                Some(guild_id) => guild_id.clone(),
+                None => {
+                    let guild_id = link_guild_id(&ctx.data.discord_http, &channel_id).await;
+                    channel_guilds.insert(channel_id.clone(), guild_id.clone());
+                    guild_id
+                }
+            };
+
+            if let Some(guild_id) = guild_id {
+                line.push_str(&format!(
+                    " ([ping](https://discord.com/channels/{guild_id}/{channel_id}/{message_id}))"
+                ));
+            }
+        }
+
+        // discord message content limit
+        if content.len() + line.len() + 1 > 2000 {
+            break;
+        }
+
+        content.push_str(&line);
+        content.push('\n');
+    }
+
+    if content.is_empty() {
+        content = "No matched deals yet".to_owned();
+    }
+
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&content))
+        .await?;
+
+    Ok(())
+}
+
+/// Guild part of a message link to the channel, "@me" for dms, `None` if the channel is gone
+async fn link_guild_id(discord_http: &DiscordHttpClient, channel_id: &str) -> Option<String> {
+    let channel_id = Id::<ChannelMarker>::new_checked(channel_id.parse().ok()?)?;
+    let channel = match discord_http.channel(channel_id).await {
+        Ok(response) => response.model().await.ok()?,
+        Err(e) => {
+            tracing::warn!("error looking up channel {channel_id}: {e}");
+            return None;
+        }
+    };
+
+    Some(
+        channel
+            .guild_id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "@me".to_owned()),
+    )
+}
+
+#[instrument(skip(ctx))]
+#[command("search")]
+#[description = "find deals by coupon code or text..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_search(
+    ctx: &SlashContext<Arc<BotContext>>,
+    #[description = "coupon code or part of it"] code: Option<String>,
+    #[description = "words in the title or description"] text: Option<String>,
+) -> DefaultCommandResult {
+    let response = InteractionResponseDataBuilder::default().flags(MessageFlags::EPHEMERAL);
+    ctx.interaction_client
+        .create_response(
+            ctx.interaction.id,
+            &ctx.interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: Some(response.build()),
+            },
+        )
+        .await?;
+
+    let code = code.map(|c| c.trim().to_owned()).filter(|c| !c.is_empty());
+    let text = text.map(|t| t.trim().to_owned()).filter(|t| !t.is_empty());
+    if code.is_none() && text.is_none() {
+        ctx.interaction_client
+            .update_response(&ctx.interaction.token)
+            .content(Some("Search needs a code or some text"))
+            .await?;
+
+        return Ok(());
+    }
+
+    let pattern = code.as_ref().map(|code| {
+        format!(
+            "%{}%",
+            code.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        )
+    });
+
+    let posts = sqlx::query!(
+        r#"
+        SELECT op.title, op.link, op.publication_date, op.coupon_codes FROM ozbargain_posts AS op
+        WHERE ($1::TEXT IS NULL
+                OR EXISTS (SELECT 1 FROM UNNEST(op.coupon_codes) AS c(code) WHERE c.code ILIKE $1))
+            AND ($2::TEXT IS NULL
+                OR to_tsvector('english', op.title || ' ' || COALESCE(op.description_text, ''))
+                    @@ websearch_to_tsquery('english', $2))
+        ORDER BY op.publication_date DESC
+        LIMIT 10
+    "#,
+        pattern,
+        text
+    )
+    .fetch_all(&ctx.data.pool)
+    .await?;
+
+    let mut content = String::new();
+    for post in posts {
+        let codes = post
+            .coupon_codes
+            .iter()
+            .map(|code| format!("`{code}`"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let line = format!(
+            "<t:{}:R> [{}](<{}>) - {}",
+            post.publication_date.and_utc().timestamp(),
+            post.title,
+            post.link,
+            codes
+        );
+
+        // discord message content limit
+        if content.len() + line.len() + 1 > 2000 {
+            break;
+        }
+
+        content.push_str(&line);
+        content.push('\n');
+    }
+
+    if content.is_empty() {
+        content = "No deals found".to_owned();
+    }
+
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&content))
+        .await?;
+
+    Ok(())
+}
+
+#[autocomplete]
+async fn autocomplete_notification_formats(
+    _ctx: AutocompleteContext<Arc<BotContext>>,
+) -> Option<InteractionResponseData> {
+    let choices = NotificationFormat::ALL
+        .iter()
+        .map(|format| CommandOptionChoice {
+            name: format.as_str().to_owned(),
+            name_localizations: None,
+            value: CommandOptionChoiceValue::String(format.as_str().to_owned()),
+        })
+        .collect();
+
+    Some(InteractionResponseData {
+        choices: Some(choices),
+        ..Default::default()
+    })
+}
+
+#[instrument(skip(ctx))]
+#[command("template")]
+#[description = "change how notifications for a keyword look..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_settings_template(
+    ctx: &SlashContext<Arc<BotContext>>,
+    #[autocomplete(autocomplete_existing_keywords)]
+    #[description = "keyword to change"]
+    selection: String,
+    #[autocomplete(autocomplete_notification_formats)]
+    #[description = "compact, standard, detailed or custom"]
+    format: String,
+    #[description = "custom template e.g. {title} for {price} {link}"] template: Option<String>,
+) -> DefaultCommandResult {
+    let response = InteractionResponseDataBuilder::default().flags(MessageFlags::EPHEMERAL);
+    ctx.interaction_client
+        .create_response(
+            ctx.interaction.id,
+            &ctx.interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: Some(response.build()),
+            },
+        )
+        .await?;
+
+    let discord_id = ctx
+        .interaction
+        .author_id()
+        .context("must have author")?
+        .to_string();
+
+    let validated =
+        format
+            .parse::<NotificationFormat>()
+            .and_then(|format| match (format, &template) {
+                (NotificationFormat::Custom, Some(template)) => {
+                    validate_template(template).map(|_| format)
+                }
+                (NotificationFormat::Custom, None) => Err(anyhow::Error::msg(
+                    "a template is required for custom format",
+                )),
+                (_, _) => Ok(format),
+            });
+
+    let format = match validated {
+        Ok(format) => format,
+        Err(e) => {
+            ctx.interaction_client
+                .update_response(&ctx.interaction.token)
+                .content(Some(&format!("Invalid template: {e}")))
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let template = template.filter(|_| format == NotificationFormat::Custom);
+    let updated_item = sqlx::query!(
+        r#"
+        UPDATE registered_keywords AS rk SET notification_format = $3, notification_template = $4
+        FROM discord_users AS du
+        WHERE rk.discord_user_id = du.id AND rk.id = $1 AND du.discord_id = $2
+        RETURNING rk.keyword
+    "#,
+        selection.parse::<i32>()?,
+        discord_id,
+        format.as_str(),
+        template
+    )
+    .fetch_optional(&ctx.data.pool)
+    .await?;
+
+    let content = match updated_item {
+        Some(item) => format!(
+            "Notifications for \"{}\" will use the {} format",
+            item.keyword,
+            format.as_str()
+        ),
+        None => "This keyword is not registered to you".to_owned(),
+    };
+
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&content))
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx))]
+#[command("renotify")]
+#[description = "get notified again when a matched deal drops in price or is re-posted..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_settings_renotify(
+    ctx: &SlashContext<Arc<BotContext>>,
+    #[autocomplete(autocomplete_existing_keywords)]
+    #[description = "keyword to change"]
+    selection: String,
+    #[description = "notify again on price drops and re-posts"] enabled: bool,
+) -> DefaultCommandResult {
+    let response = InteractionResponseDataBuilder::default().flags(MessageFlags::EPHEMERAL);
+    ctx.interaction_client
+        .create_response(
+            ctx.interaction.id,
+            &ctx.interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: Some(response.build()),
+            },
+        )
+        .await?;
+
+    let discord_id = ctx
+        .interaction
+        .author_id()
+        .context("must have author")?
+        .to_string();
+
+    let updated_item = sqlx::query!(
+        r#"
+        UPDATE registered_keywords AS rk SET renotify = $3
+        FROM discord_users AS du
+        WHERE rk.discord_user_id = du.id AND rk.id = $1 AND du.discord_id = $2
+        RETURNING rk.keyword
+    "#,
+        selection.parse::<i32>()?,
+        discord_id,
+        enabled
+    )
+    .fetch_optional(&ctx.data.pool)
+    .await?;
+
+    let content = match (updated_item, enabled) {
+        (Some(item), true) => format!(
+            "You will be notified again when deals matched by \"{}\" drop in price or are re-posted",
+            item.keyword
+        ),
+        (Some(item), false) => format!(
+            "Deals matched by \"{}\" will only be notified once",
+            item.keyword
+        ),
+        (None, _) => "This keyword is not registered to you".to_owned(),
+    };
+
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&content))
+        .await?;
+
+    Ok(())
+}
+
+/// Defers an ephemeral response, returns the guild id when the user may manage the guild
+async fn defer_guild_admin(
+    ctx: &SlashContext<Arc<BotContext>>,
+) -> Result<Option<String>, anyhow::Error> {
+    let response = InteractionResponseDataBuilder::default().flags(MessageFlags::EPHEMERAL);
+    ctx.interaction_client
+        .create_response(
+            ctx.interaction.id,
+            &ctx.interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: Some(response.build()),
+            },
+        )
+        .await?;
+
+    // member permissions are only sent for interactions in a guild
+    let can_manage_guild = ctx
+        .interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.contains(Permissions::MANAGE_GUILD));
+
+    match ctx.interaction.guild_id {
+        Some(guild_id) if can_manage_guild => Ok(Some(guild_id.to_string())),
+        _ => {
+            ctx.interaction_client
+                .update_response(&ctx.interaction.token)
+                .content(Some(
+                    "Server settings need the Manage Server permission in a server",
+                ))
+                .await?;
+
+            Ok(None)
+        }
+    }
+}
+
+/// Whether the channel is in the guild, a channel from another server would let anyone ping there
+async fn channel_in_guild(
+    discord_http: &DiscordHttpClient,
+    channel_id: &str,
+    guild_id: Option<Id<GuildMarker>>,
+) -> bool {
+    let Some(channel_id) = channel_id
+        .parse()
+        .ok()
+        .and_then(Id::<ChannelMarker>::new_checked)
+    else {
+        return false;
+    };
+
+    let channel = match discord_http.channel(channel_id).await {
+        Ok(response) => response.model().await.ok(),
+        Err(e) => {
+            tracing::warn!("error looking up channel {channel_id}: {e}");
+            None
+        }
+    };
+
+    channel.is_some_and(|c| c.guild_id.is_some() && c.guild_id == guild_id)
+}
+
+fn channel_list(channel_ids: &[String]) -> String {
+    if channel_ids.is_empty() {
+        return "any channel".to_owned();
+    }
+
+    channel_ids
+        .iter()
+        .map(|id| format!("<#{id}>"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[instrument(skip(ctx))]
+#[command("allow-channel")]
+#[description = "let /register notify a channel, once any are allowed others are not..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_admin_allow_channel(
+    ctx: &SlashContext<Arc<BotContext>>,
+    #[description = "channel to allow"] channel: Id<ChannelMarker>,
+) -> DefaultCommandResult {
+    let Some(guild_id) = defer_guild_admin(ctx).await? else {
+        return Ok(());
+    };
+
+    if !channel_in_guild(
+        &ctx.data.discord_http,
+        &channel.to_string(),
+        ctx.interaction.guild_id,
+    )
+    .await
+    {
+        ctx.interaction_client
+            .update_response(&ctx.interaction.token)
+            .content(Some("Only channels in this server can be allowed"))
+            .await?;
+
+        return Ok(());
+    }
+
+    let settings = sqlx::query!(
+        r#"
+        INSERT INTO guild_settings (guild_id, allowed_channel_ids) VALUES ($1, ARRAY[$2])
+        ON CONFLICT (guild_id) DO UPDATE
+        SET allowed_channel_ids = array_append(array_remove(guild_settings.allowed_channel_ids, $2), $2),
+            updated_at = now()
+        RETURNING allowed_channel_ids
+    "#,
+        guild_id,
+        channel.to_string()
+    )
+    .fetch_one(&ctx.data.pool)
+    .await?;
+
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&format!(
+            "Keywords may notify: {}",
+            channel_list(&settings.allowed_channel_ids)
+        )))
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx))]
+#[command("disallow-channel")]
+#[description = "stop /register from notifying a channel..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_admin_disallow_channel(
+    ctx: &SlashContext<Arc<BotContext>>,
+    #[description = "channel to disallow"] channel: Id<ChannelMarker>,
+) -> DefaultCommandResult {
+    let Some(guild_id) = defer_guild_admin(ctx).await? else {
+        return Ok(());
+    };
+
+    let settings = sqlx::query!(
+        r#"
+        UPDATE guild_settings SET allowed_channel_ids = array_remove(allowed_channel_ids, $2), updated_at = now()
+        WHERE guild_id = $1
+        RETURNING allowed_channel_ids
+    "#,
+        guild_id,
+        channel.to_string()
+    )
+    .fetch_optional(&ctx.data.pool)
+    .await?;
+
+    let allowed_channel_ids = settings.map(|s| s.allowed_channel_ids).unwrap_or_default();
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&format!(
+            "Keywords may notify: {}",
+            channel_list(&allowed_channel_ids)
+        )))
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx))]
+#[command("quota")]
+#[description = "limit how many keywords each user can register in this server..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_admin_quota(
+    ctx: &SlashContext<Arc<BotContext>>,
+    #[description = "keywords per user, leave empty for no limit"] limit: Option<i64>,
+) -> DefaultCommandResult {
+    let Some(guild_id) = defer_guild_admin(ctx).await? else {
+        return Ok(());
+    };
+
+    let limit = limit.map(|l| l.clamp(0, i32::MAX as i64) as i32);
+    sqlx::query!(
+        r#"
+        INSERT INTO guild_settings (guild_id, keyword_quota) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET keyword_quota = EXCLUDED.keyword_quota, updated_at = now()
+    "#,
+        guild_id,
+        limit
+    )
+    .execute(&ctx.data.pool)
+    .await?;
+
+    let content = match limit {
+        Some(limit) => format!("Users can register up to {limit} keywords in this server"),
+        None => "Users can register any number of keywords in this server".to_owned(),
+    };
+
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&content))
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx))]
+#[command("default-channel")]
+#[description = "channel /register notifies when none is given..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_admin_default_channel(
+    ctx: &SlashContext<Arc<BotContext>>,
+    #[description = "default channel, leave empty to notify where /register is used"]
+    channel: Option<Id<ChannelMarker>>,
+) -> DefaultCommandResult {
+    let Some(guild_id) = defer_guild_admin(ctx).await? else {
+        return Ok(());
+    };
+
+    let channel_id = channel.map(|c| c.to_string());
+    if let Some(channel_id) = &channel_id {
+        if !channel_in_guild(&ctx.data.discord_http, channel_id, ctx.interaction.guild_id).await {
+            ctx.interaction_client
+                .update_response(&ctx.interaction.token)
+                .content(Some("The default channel must be in this server"))
+                .await?;
+
+            return Ok(());
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO guild_settings (guild_id, default_channel_id) VALUES ($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET default_channel_id = EXCLUDED.default_channel_id, updated_at = now()
+    "#,
+        guild_id,
+        channel_id
+    )
+    .execute(&ctx.data.pool)
+    .await?;
+
+    let content = match channel_id {
+        Some(channel_id) => format!("New keywords will notify <#{channel_id}> by default"),
+        None => "New keywords will notify the channel /register is used in".to_owned(),
+    };
+
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&content))
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip(ctx))]
+#[command("show")]
+#[description = "show this server's settings..."]
+#[error_handler(handle_interaction_error)]
+async fn handle_admin_show(ctx: &SlashContext<Arc<BotContext>>) -> DefaultCommandResult {
+    let Some(guild_id) = defer_guild_admin(ctx).await? else {
+        return Ok(());
+    };
+
+    let settings = sqlx::query!("SELECT * FROM guild_settings WHERE guild_id = $1", guild_id)
+        .fetch_optional(&ctx.data.pool)
+        .await?;
+
+    let content = match settings {
+        Some(settings) => format!(
+            "Allowed channels: {}\nKeyword quota: {}\nDefault channel: {}",
+            channel_list(&settings.allowed_channel_ids),
+            settings
+                .keyword_quota
+                .map(|q| q.to_string())
+                .unwrap_or_else(|| "unlimited".to_owned()),
+            settings
+                .default_channel_id
+                .map(|id| format!("<#{id}>"))
+                .unwrap_or_else(|| "where /register is used".to_owned())
+        ),
+        None => "No settings, keywords can notify any channel without a quota".to_owned(),
+    };
+
+    ctx.interaction_client
+        .update_response(&ctx.interaction.token)
+        .content(Some(&content))
+        .await?;
+
+    Ok(())
+}
+
 pub async fn run_discord_bot() -> Result<(), anyhow::Error> {
     let database_url = std::env::var("DATABASE_URL")?;
     let discord_token = std::env::var("DISCORD_TOKEN")?;
+    // keywords each user may register across all servers, guilds may set a lower quota
+    let keyword_quota = std::env::var("KEYWORD_QUOTA")
+        .ok()
+        .map(|q| q.parse::<i64>())
+        .transpose()
+        .context("KEYWORD_QUOTA must be a number")?
+        .unwrap_or(DEFAULT_KEYWORD_QUOTA);
 
     let discord_http = Arc::new(DiscordHttpClient::new(discord_token.to_owned()));
     let standby = Standby::new();
@@ -292,14 +1205,15 @@ pub async fn run_discord_bot() -> Result<(), anyhow::Error> {
         Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT | Intents::GUILDS,
     );
 
-    tracing::info!("connecting to db: {database_url}");
+    tracing::info!(
+        "connecting to db: {}",
+        redact_connection_string(&database_url)
+    );
     let pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(&database_url)
         .await?;
 
-    let bot_context = Arc::new(BotContext { pool, standby });
-
     let cache = InMemoryCache::<DefaultCacheModels>::builder()
         .resource_types(ResourceType::MESSAGE | ResourceType::GUILD)
         .build();
@@ -311,10 +1225,37 @@ pub async fn run_discord_bot() -> Result<(), anyhow::Error> {
         .await?
         .id;
 
+    let bot_context = Arc::new(BotContext {
+        pool,
+        standby,
+        discord_http: discord_http.clone(),
+        app_id,
+        keyword_quota,
+    });
+
     let framework = Arc::new(
         Framework::builder(discord_http.clone(), app_id, bot_context.clone())
             .command(handle_register_keywords)
             .command(handle_unregister_keywords)
+            .command(handle_history)
+            .command(handle_search)
+            .group(|group| {
+                group
+                    .name("settings")
+                    .description("change your notification settings...")
+                    .command(handle_settings_template)
+                    .command(handle_settings_renotify)
+            })
+            .group(|group| {
+                group
+                    .name("admin")
+                    .description("change settings for this server...")
+                    .command(handle_admin_allow_channel)
+                    .command(handle_admin_disallow_channel)
+                    .command(handle_admin_quota)
+                    .command(handle_admin_default_channel)
+                    .command(handle_admin_show)
+            })
             .build(),
     );
 
@@ -349,22 +1290,139 @@ pub async fn run_discord_bot() -> Result<(), anyhow::Error> {
 
         bot_context.standby.process(&event);
         tokio::spawn(
-            handle_event(event, Arc::clone(&framework)).then(|result| async {
-                match result {
-                    Ok(_) => {}
-                    Err(e) => tracing::error!("{}", e),
-                }
-            }),
+            handle_event(event, Arc::clone(&framework), Arc::clone(&bot_context)).then(
+                |result| async {
+                    match result {
+                        Ok(_) => {}
+                        Err(e) => tracing::error!("{}", e),
+                    }
+                },
+            ),
         );
     }
 
     Ok(())
 }
 
+#[instrument(skip_all)]
+async fn handle_notification_action(
+    interaction: Interaction,
+    bot_context: Arc<BotContext>,
+) -> Result<(), anyhow::Error> {
+    let Some(InteractionData::MessageComponent(ref data)) = interaction.data else {
+        return Ok(());
+    };
+
+    // other components (e.g. the /register category select) are handled through standby
+    let Some(action) = NotificationAction::parse(&data.custom_id) else {
+        return Ok(());
+    };
+
+    let discord_id = interaction
+        .author_id()
+        .context("must have author")?
+        .to_string();
+
+    let pool = &bot_context.pool;
+    let content = match action {
+        NotificationAction::Unregister { keyword_id } => {
+            let deleted_item = sqlx::query!(
+                r#"
+                DELETE FROM registered_keywords AS rk USING discord_users AS du
+                WHERE rk.discord_user_id = du.id AND rk.id = $1 AND du.discord_id = $2
+                RETURNING rk.keyword
+            "#,
+                keyword_id,
+                discord_id
+            )
+            .fetch_optional(pool)
+            .await?;
+
+            match deleted_item {
+                Some(item) => format!("Removed \"{}\" as keyword for search", item.keyword),
+                None => "This keyword is not registered to you".to_owned(),
+            }
+        }
+        NotificationAction::Snooze { keyword_id } => {
+            let snoozed_item = sqlx::query!(
+                r#"
+                UPDATE registered_keywords AS rk SET snoozed_until = now() + interval '24 hours'
+                FROM discord_users AS du
+                WHERE rk.discord_user_id = du.id AND rk.id = $1 AND du.discord_id = $2
+                RETURNING rk.keyword
+            "#,
+                keyword_id,
+                discord_id
+            )
+            .fetch_optional(pool)
+            .await?;
+
+            match snoozed_item {
+                Some(item) => format!("Snoozed \"{}\" for 24 hours", item.keyword),
+                None => "This keyword is not registered to you".to_owned(),
+            }
+        }
+        NotificationAction::MuteMerchant { post_id } => {
+            let post = sqlx::query!("SELECT title FROM ozbargain_posts WHERE id = $1", post_id)
+                .fetch_one(pool)
+                .await?;
+
+            let merchant = merchant(&post.title).context("post must have merchant")?;
+
+            let mut transaction = pool.begin().await?;
+            sqlx::query!(
+                "INSERT INTO discord_users(discord_id) VALUES ($1) ON CONFLICT DO NOTHING",
+                discord_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+
+            let discord_user_id = sqlx::query!(
+                "SELECT id FROM discord_users WHERE discord_id = $1",
+                discord_id
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            sqlx::query!(
+                "INSERT INTO muted_merchants (discord_user_id, merchant) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                discord_user_id.id,
+                merchant
+            )
+            .execute(&mut *transaction)
+            .await?;
+
+            transaction.commit().await?;
+
+            format!("You will no longer be notified for deals from {}", merchant)
+        }
+    };
+
+    let response = InteractionResponseDataBuilder::default()
+        .flags(MessageFlags::EPHEMERAL)
+        .content(content);
+
+    bot_context
+        .discord_http
+        .interaction(bot_context.app_id)
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(response.build()),
+            },
+        )
+        .await?;
+
+    Ok(())
+}
+
 #[instrument(skip_all)]
 async fn handle_event(
     event: Event,
     framework: Arc<Framework<Arc<BotContext>>>,
+    bot_context: Arc<BotContext>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     if let Event::InteractionCreate(i) = event {
         match i.kind {
@@ -373,6 +1431,9 @@ async fn handle_event(
                 let inner = i.0;
                 framework.process(inner).await;
             }
+            InteractionType::MessageComponent => {
+                handle_notification_action(i.0, bot_context).await?;
+            }
             kind => tracing::info!("ignoring interaction type: {:?}", kind),
         }
     }
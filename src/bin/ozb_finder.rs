@@ -1,14 +1,14 @@
-use anyhow::Context;
-use ozb::ozbargain::OZB_RSS_DEALS_URL;
-use ozb::{skip_option, skip_result};
-use reqwest::header::USER_AGENT;
-use reqwest::{
-    header::{ETAG, IF_NONE_MATCH},
-    StatusCode,
-};
+use ozb::feed::{parse_feed, FeedAdapter, FeedItem, FieldMapping};
+use ozb::fetch_policy::FetchPolicy;
+use ozb::fetcher::{Fetcher, FetcherConfig};
+use ozb::ozbargain::{coupon_codes, description_text, is_homepage, merchant, product_key};
+use ozb::util::redact_connection_string;
+use reqwest::StatusCode;
 use sqlx::postgres::PgPoolOptions;
-use std::collections::HashMap;
+use sqlx::{Pool, Postgres};
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
+use tokio::time::Instant;
 use tracing::Level;
 use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -20,158 +20,528 @@ async fn main() -> Result<(), anyhow::Error> {
         .init();
 
     let database_url = std::env::var("DATABASE_URL")?;
+    // discord webhook told about categories created from the feed
+    let admin_webhook_url = std::env::var("ADMIN_WEBHOOK_URL").ok();
 
-    tracing::info!("connecting to db: {database_url}");
+    tracing::info!(
+        "connecting to db: {}",
+        redact_connection_string(&database_url)
+    );
     let pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(&database_url)
         .await?;
 
     sqlx::migrate!("./migrations").run(&pool).await?;
+    backfill_description_text(&pool).await?;
 
-    let http_client = reqwest::ClientBuilder::new().cookie_store(true).build()?;
+    let fetcher = Fetcher::new(FetcherConfig::from_env()?)?;
 
-    let mut stored_etag: Option<String> = None;
+    // each source is polled on its own schedule with its own backoff
+    let mut schedule = HashMap::<i32, (FetchPolicy, Instant)>::new();
     loop {
-        tracing::info!("fetching rss");
-        let response = http_client
-            .get(OZB_RSS_DEALS_URL)
-            .header(
-                USER_AGENT,
-                "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0",
-            )
-            .header(IF_NONE_MATCH, stored_etag.clone().unwrap_or_default())
-            .send()
-            .await?;
-
-        if response.status() == StatusCode::NOT_MODIFIED {
-            tracing::info!("304 response, skipping...");
-            tracing::info!("sleeping :)");
-            tokio::time::sleep(Duration::from_secs(60)).await;
-            continue;
-        } else if response.status() == StatusCode::FORBIDDEN {
-            tracing::info!("blocked by cf");
-            tokio::time::sleep(Duration::from_secs(60)).await;
-            continue;
-        } else {
-            let resp_headers = response.headers();
-            let etag = resp_headers
-                .get(ETAG)
-                .map(|h| h.to_str().unwrap_or_default().to_owned());
-
-            stored_etag = etag;
-        }
+        let feed_sources = match sqlx::query!(
+            r#"SELECT id, name, url, poll_interval_seconds, adapter, guid_field, categories_field, thumbnail_field
+                FROM feed_sources WHERE enabled = true"#
+        )
+        .fetch_all(&pool)
+        .await
+        {
+            Ok(feed_sources) => feed_sources,
+            Err(e) => {
+                tracing::error!("error loading feed sources: {e}");
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                continue;
+            }
+        };
 
-        tracing::info!("status: {}", response.status());
-        let response = response.bytes().await?;
-        tracing::info!("response: {response:?}",);
-        let channel = rss::Channel::read_from(&response[..])?;
-
-        for item in channel.items() {
-            let guid = skip_option!(item.guid(), "guid")
-                .value()
-                .split_ascii_whitespace()
-                .next();
-
-            let guid = skip_option!(guid, "guid whitespace").to_owned();
-            let title = skip_option!(item.title(), "title").to_owned();
-            // Sun, 26 Mar 2023 17:29:29 +1100
-            let publication_date = skip_result!(
-                chrono::DateTime::parse_from_str(
-                    skip_option!(item.pub_date(), "publication date"),
-                    "%a, %d %b %Y %T %z",
-                ),
-                "publication date"
-            )
-            .naive_utc();
-            let link = skip_option!(item.link(), "link").to_owned();
-            let description = skip_option!(item.description(), "description").to_owned();
-            let ext = item.extensions();
-            let thumbnail = || -> Result<String, anyhow::Error> {
-                Ok(ext
-                    .get("media")
-                    .context("must have thumbnail")?
-                    .get("thumbnail")
-                    .context("must have url 1")?
-                    .first()
-                    .context("must have url 2")?
-                    .attrs
-                    .get("url")
-                    .context("must have url 3")?
-                    .to_owned())
-            };
+        schedule.retain(|id, _| feed_sources.iter().any(|s| s.id == *id));
 
-            let categories: Vec<_> = item
-                .categories()
-                .iter()
-                .map(|c| c.name.to_owned().replace("&amp;", "&"))
-                .collect();
+        for feed_source in &feed_sources {
+            let (fetch_policy, next_poll) = schedule
+                .entry(feed_source.id)
+                .or_insert_with(|| (FetchPolicy::default(), Instant::now()));
 
-            let already_exists = sqlx::query!(
-                "SELECT ozbargain_id FROM ozbargain_posts WHERE ozbargain_id = $1",
-                &guid
-            )
-            .fetch_optional(&pool)
-            .await?
-            .is_some();
+            fetch_policy.interval =
+                Duration::from_secs(feed_source.poll_interval_seconds.max(1) as u64);
 
-            if already_exists {
-                tracing::warn!("this post already exists: {}", guid);
+            if *next_poll > Instant::now() {
                 continue;
             }
 
-            let mut transaction = pool.begin().await?;
-
-            let post = sqlx::query!(
-                r#"INSERT INTO ozbargain_posts
-                    (title,
-                     description,
-                     thumbnail,
-                     link,
-                     ozbargain_id,
-                     publication_date,
-                     notified)
-                    VALUES ($1, $2, $3, $4, $5, $6, false)
-                    ON CONFLICT (ozbargain_id) DO NOTHING
-                    RETURNING id"#,
-                title,
-                description,
-                thumbnail().ok(),
-                link,
-                &guid,
-                publication_date
-            )
-            .fetch_one(&mut *transaction)
-            .await?;
+            let adapter = match feed_source.adapter.parse::<FeedAdapter>() {
+                Ok(adapter) => adapter,
+                Err(e) => {
+                    // not skipped outright, the poll would be due again straight away
+                    tracing::error!(source = feed_source.name, "invalid feed source: {e}");
+                    *next_poll = Instant::now() + fetch_policy.interval;
+                    continue;
+                }
+            };
+            let mapping = FieldMapping {
+                guid: feed_source.guid_field.clone(),
+                categories: feed_source.categories_field.clone(),
+                thumbnail: feed_source.thumbnail_field.clone(),
+            };
 
-            let category_id_map = sqlx::query!(
-                "SELECT id, name FROM categories WHERE name = ANY($1)",
-                &categories
+            fetch_policy.before_fetch();
+            let delay = match poll_feed(
+                &fetcher,
+                &pool,
+                feed_source.id,
+                &feed_source.url,
+                adapter,
+                &mapping,
+                admin_webhook_url.as_deref(),
             )
-            .fetch_all(&mut *transaction)
-            .await?
-            .into_iter()
-            .map(|r| (r.name, r.id))
-            .collect::<HashMap<String, i32>>();
-
-            // FIXME:
-            for category in categories {
-                if let Some(category_id) = category_id_map.get(&category) {
-                    sqlx::query!(
-                        "INSERT INTO category_association (category_id, post_id) VALUES ($1, $2)",
-                        category_id,
-                        post.id
-                    )
-                    .execute(&mut *transaction)
-                    .await?;
+            .await
+            {
+                Ok(()) => fetch_policy.record_success(),
+                Err(e) => {
+                    let delay = fetch_policy.record_failure();
+                    tracing::error!(
+                        source = feed_source.name,
+                        failures = fetch_policy.failures(),
+                        circuit = ?fetch_policy.state(),
+                        "error polling feed, retrying in {delay:?}: {e}"
+                    );
+
+                    delay
                 }
-            }
+            };
 
-            transaction.commit().await?;
-            tracing::info!("inserted: {}", guid);
+            tracing::info!(source = feed_source.name, circuit = ?fetch_policy.state(), "next poll in {delay:?}");
+            *next_poll = Instant::now() + delay;
         }
 
+        let next_poll = schedule
+            .values()
+            .map(|(_, next_poll)| *next_poll)
+            .min()
+            .unwrap_or_else(|| Instant::now() + Duration::from_secs(60));
+
         tracing::info!("sleeping :)");
-        tokio::time::sleep(Duration::from_secs(60)).await;
+        tokio::time::sleep_until(next_poll).await;
     }
 }
+
+/// Stores the plain text of posts stored before `description_text` was added,
+/// so they're in `/search`'s full text index too
+async fn backfill_description_text(pool: &Pool<Postgres>) -> Result<(), anyhow::Error> {
+    let mut backfilled = 0;
+    loop {
+        let posts = sqlx::query!(
+            "SELECT id, description FROM ozbargain_posts WHERE description_text IS NULL ORDER BY id LIMIT 500"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        if posts.is_empty() {
+            break;
+        }
+
+        let ids = posts.iter().map(|p| p.id).collect::<Vec<_>>();
+        let texts = posts
+            .iter()
+            .map(|p| description_text(&p.description))
+            .collect::<Vec<_>>();
+        sqlx::query!(
+            r#"UPDATE ozbargain_posts AS op SET description_text = d.text
+                FROM UNNEST($1::INTEGER[], $2::TEXT[]) AS d(id, text) WHERE op.id = d.id"#,
+            &ids,
+            &texts
+        )
+        .execute(pool)
+        .await?;
+
+        backfilled += posts.len();
+    }
+
+    if backfilled > 0 {
+        tracing::info!("backfilled the description text of {backfilled} posts");
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(fetcher, pool, mapping, admin_webhook_url))]
+async fn poll_feed(
+    fetcher: &Fetcher,
+    pool: &Pool<Postgres>,
+    feed_source_id: i32,
+    url: &str,
+    adapter: FeedAdapter,
+    mapping: &FieldMapping,
+    admin_webhook_url: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let feed_state = sqlx::query!(
+        "SELECT etag, last_modified FROM feed_state WHERE url = $1",
+        url
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let (etag, last_modified) = feed_state
+        .map(|s| (s.etag, s.last_modified))
+        .unwrap_or_default();
+
+    tracing::info!("fetching rss");
+    let started = Instant::now();
+    let fetched = fetcher
+        .fetch(url, etag.as_deref(), last_modified.as_deref())
+        .await?;
+
+    if fetched.status == StatusCode::NOT_MODIFIED {
+        tracing::info!(
+            status = %fetched.status,
+            duration_ms = started.elapsed().as_millis() as u64,
+            "feed not modified, skipping..."
+        );
+        return Ok(());
+    } else if fetched.status == StatusCode::FORBIDDEN {
+        anyhow::bail!("blocked by cf");
+    } else if !fetched.status.is_success() {
+        anyhow::bail!("unexpected status: {}", fetched.status);
+    }
+
+    let mut items = parse_feed(adapter, mapping, &fetched.body)?;
+    // guids are unique across posts, other feeds' could collide with OzBargain node ids or each other
+    if adapter != FeedAdapter::OzBargain {
+        for item in &mut items {
+            item.guid = format!("{feed_source_id}:{}", item.guid);
+        }
+    }
+
+    let item_count = items.len();
+
+    let InsertedItems {
+        new_guids,
+        updated_guids,
+        requeued_guids,
+        new_categories,
+    } = insert_items(pool, feed_source_id, adapter, items).await?;
+    tracing::info!(
+        status = %fetched.status,
+        bytes = fetched.body.len(),
+        items = item_count,
+        new = new_guids.len(),
+        updated = updated_guids.len(),
+        requeued = requeued_guids.len(),
+        duration_ms = started.elapsed().as_millis() as u64,
+        "fetched feed"
+    );
+    tracing::debug!(
+        "new posts: {new_guids:?}, updated posts: {updated_guids:?}, requeued posts: {requeued_guids:?}"
+    );
+
+    if !new_categories.is_empty() {
+        tracing::warn!("created new categories: {:?}", new_categories);
+        if let Some(admin_webhook_url) = admin_webhook_url {
+            let content = format!(
+                "Created new categories from the feed, they may need an emoji: {}",
+                new_categories.join(", ")
+            );
+
+            // the categories are already stored, so a failed notification isn't retried
+            if let Err(e) = fetcher
+                .client()
+                .post(admin_webhook_url)
+                .json(&HashMap::from([("content", content)]))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+            {
+                tracing::error!("error notifying admin of new categories: {e}");
+            }
+        }
+    }
+
+    // only stored once every item is inserted, so a failure above refetches the feed
+    sqlx::query!(
+        r#"INSERT INTO feed_state (url, etag, last_modified, updated_at)
+            VALUES ($1, $2, $3, now())
+            ON CONFLICT (url) DO UPDATE
+            SET etag = EXCLUDED.etag, last_modified = EXCLUDED.last_modified, updated_at = now()"#,
+        url,
+        fetched.etag,
+        fetched.last_modified
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+struct InsertedItems {
+    new_guids: Vec<String>,
+    /// existing posts that changed, their previous version is kept as a revision
+    updated_guids: Vec<String>,
+    /// existing posts seen in this feed for the first time, matched again for it
+    requeued_guids: Vec<String>,
+    new_categories: Vec<String>,
+}
+
+/// Upserts the whole feed in one transaction
+async fn insert_items(
+    pool: &Pool<Postgres>,
+    feed_source_id: i32,
+    adapter: FeedAdapter,
+    items: Vec<FeedItem>,
+) -> Result<InsertedItems, anyhow::Error> {
+    let mut seen = HashSet::new();
+    let items = items
+        .into_iter()
+        .filter(|item| seen.insert(item.guid.clone()))
+        .collect::<Vec<_>>();
+
+    // tags of other feeds are free form, only existing categories are kept so they
+    // don't count as a change against the stored associations on every poll
+    let known_categories = if adapter == FeedAdapter::OzBargain {
+        None
+    } else {
+        let categories = sqlx::query!("SELECT name FROM categories")
+            .fetch_all(pool)
+            .await?;
+
+        Some(
+            categories
+                .into_iter()
+                .map(|c| c.name)
+                .collect::<HashSet<_>>(),
+        )
+    };
+
+    let mut titles = Vec::with_capacity(items.len());
+    let mut descriptions = Vec::with_capacity(items.len());
+    let mut description_texts = Vec::with_capacity(items.len());
+    let mut thumbnails = Vec::with_capacity(items.len());
+    let mut links = Vec::with_capacity(items.len());
+    let mut guids = Vec::with_capacity(items.len());
+    let mut publication_dates = Vec::with_capacity(items.len());
+    let mut votes_positive = Vec::with_capacity(items.len());
+    let mut votes_negative = Vec::with_capacity(items.len());
+    let mut deal_urls = Vec::with_capacity(items.len());
+    let mut product_keys = Vec::with_capacity(items.len());
+    let mut joined_coupon_codes = Vec::with_capacity(items.len());
+    let mut joined_categories = Vec::with_capacity(items.len());
+    let mut category_guids = Vec::new();
+    let mut category_names = Vec::new();
+
+    for mut item in items {
+        if let Some(known_categories) = &known_categories {
+            item.categories.retain(|c| known_categories.contains(c));
+        }
+        item.categories.sort();
+        item.categories.dedup();
+        joined_categories.push(item.categories.join("\n"));
+
+        for category in item.categories {
+            category_guids.push(item.guid.clone());
+            category_names.push(category);
+        }
+
+        // without a merchant the title alone is too generic to link re-posts by
+        product_keys.push(merchant(&item.title).map(|_| product_key(&item.title)));
+        deal_urls.push(item.deal_url.filter(|url| !is_homepage(url)));
+        joined_coupon_codes.push(coupon_codes(&item.description).join("\n"));
+        description_texts.push(description_text(&item.description));
+        titles.push(item.title);
+        descriptions.push(item.description);
+        thumbnails.push(item.thumbnail);
+        links.push(item.link);
+        guids.push(item.guid);
+        publication_dates.push(item.publication_date);
+        votes_positive.push(item.votes.map(|(positive, _)| positive));
+        votes_negative.push(item.votes.map(|(_, negative)| negative));
+    }
+
+    let mut transaction = pool.begin().await?;
+
+    let new_categories = if adapter == FeedAdapter::OzBargain {
+        sqlx::query!(
+            r#"INSERT INTO categories (name)
+                SELECT DISTINCT UNNEST($1::TEXT[])
+                ON CONFLICT (name) DO NOTHING
+                RETURNING name"#,
+            &category_names
+        )
+        .fetch_all(&mut *transaction)
+        .await?
+        .into_iter()
+        .map(|r| r.name)
+        .collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+
+    // categories are compared sorted by byte order, same as the sort above
+    let updated_guids = sqlx::query!(
+        r#"WITH incoming AS (
+                SELECT * FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::TEXT[], $7::TEXT[])
+                    AS i(guid, title, description, link, categories, coupon_codes, description_text)
+            ),
+            existing AS (
+                SELECT op.id, op.ozbargain_id, op.title, op.description, op.link,
+                    COALESCE((
+                        SELECT string_agg(c.name, E'\n' ORDER BY c.name COLLATE "C")
+                        FROM category_association AS ca
+                        JOIN categories AS c ON ca.category_id = c.id
+                        WHERE ca.post_id = op.id
+                    ), '') AS categories
+                FROM ozbargain_posts AS op WHERE op.ozbargain_id = ANY($1)
+            ),
+            changed AS (
+                SELECT e.*, i.title AS new_title, i.description AS new_description, i.link AS new_link,
+                    i.description_text AS new_description_text,
+                    string_to_array(NULLIF(i.coupon_codes, ''), E'\n') AS new_coupon_codes
+                FROM existing AS e JOIN incoming AS i ON e.ozbargain_id = i.guid
+                WHERE e.title <> i.title
+                    OR e.description <> i.description
+                    OR e.link <> i.link
+                    OR e.categories <> i.categories
+            ),
+            revisions AS (
+                INSERT INTO ozbargain_post_revisions (post_id, title, description, link, categories)
+                SELECT id, title, description, link, string_to_array(NULLIF(categories, ''), E'\n')
+                FROM changed
+            )
+            UPDATE ozbargain_posts AS op
+            SET title = c.new_title, description = c.new_description, link = c.new_link,
+                description_text = c.new_description_text,
+                coupon_codes = COALESCE(c.new_coupon_codes, '{}'), updated_at = now()
+            FROM changed AS c WHERE op.id = c.id
+            RETURNING op.ozbargain_id"#,
+        &guids,
+        &titles,
+        &descriptions,
+        &links,
+        &joined_categories,
+        &joined_coupon_codes,
+        &description_texts
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.ozbargain_id)
+    .collect::<Vec<_>>();
+
+    let new_guids = sqlx::query!(
+        r#"INSERT INTO ozbargain_posts
+            (title,
+             description,
+             thumbnail,
+             link,
+             ozbargain_id,
+             publication_date,
+             votes_positive,
+             votes_negative,
+             deal_url,
+             product_key,
+             coupon_codes,
+             description_text)
+            SELECT p.title, p.description, p.thumbnail, p.link, p.guid, p.publication_date,
+                p.votes_positive, p.votes_negative, p.deal_url, p.product_key,
+                COALESCE(string_to_array(NULLIF(p.coupon_codes, ''), E'\n'), '{}'),
+                p.description_text
+            FROM UNNEST(
+                $1::TEXT[],
+                $2::TEXT[],
+                $3::TEXT[],
+                $4::TEXT[],
+                $5::TEXT[],
+                $6::TIMESTAMP[],
+                $7::INTEGER[],
+                $8::INTEGER[],
+                $9::TEXT[],
+                $10::TEXT[],
+                $11::TEXT[],
+                $12::TEXT[]
+            ) AS p(title, description, thumbnail, link, guid, publication_date,
+                votes_positive, votes_negative, deal_url, product_key, coupon_codes, description_text)
+            ON CONFLICT (ozbargain_id) DO NOTHING
+            RETURNING ozbargain_id"#,
+        &titles,
+        &descriptions,
+        &thumbnails as &[Option<String>],
+        &links,
+        &guids,
+        &publication_dates,
+        &votes_positive as &[Option<i32>],
+        &votes_negative as &[Option<i32>],
+        &deal_urls as &[Option<String>],
+        &product_keys as &[Option<String>],
+        &joined_coupon_codes,
+        &description_texts
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.ozbargain_id)
+    .collect::<Vec<_>>();
+
+    // posts already processed for another feed are matched again for this one,
+    // keywords notified the first time are skipped through the audit
+    let requeued_guids = sqlx::query!(
+        r#"WITH tagged AS (
+                INSERT INTO post_feed_sources (post_id, feed_source_id)
+                SELECT op.id, $2 FROM ozbargain_posts AS op WHERE op.ozbargain_id = ANY($1)
+                ON CONFLICT DO NOTHING
+                RETURNING post_id
+            )
+            UPDATE ozbargain_posts AS op
+            SET processing_state = 'pending', attempts = 0, leased_until = NULL
+            FROM tagged AS t WHERE op.id = t.post_id AND op.processing_state = 'done'
+            RETURNING op.ozbargain_id"#,
+        &guids,
+        feed_source_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.ozbargain_id)
+    .collect::<Vec<_>>();
+
+    sqlx::query!(
+        r#"UPDATE ozbargain_posts AS op SET reposted_from = (
+                SELECT prev.id FROM ozbargain_posts AS prev
+                WHERE prev.id < op.id
+                    AND prev.publication_date > op.publication_date - INTERVAL '90 days'
+                    AND (prev.deal_url = op.deal_url OR prev.product_key = op.product_key)
+                ORDER BY prev.id DESC LIMIT 1
+            )
+            WHERE op.ozbargain_id = ANY($1)"#,
+        &new_guids
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"DELETE FROM category_association AS ca USING ozbargain_posts AS op
+            WHERE ca.post_id = op.id AND op.ozbargain_id = ANY($1)"#,
+        &updated_guids
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"INSERT INTO category_association (category_id, post_id)
+            SELECT c.id, op.id FROM UNNEST($1::TEXT[], $2::TEXT[]) AS pc(guid, name)
+            JOIN ozbargain_posts AS op ON op.ozbargain_id = pc.guid
+            JOIN categories AS c ON c.name = pc.name
+            WHERE op.ozbargain_id = ANY($3)"#,
+        &category_guids,
+        &category_names,
+        &[new_guids.as_slice(), updated_guids.as_slice()].concat()
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(InsertedItems {
+        new_guids,
+        updated_guids,
+        requeued_guids,
+        new_categories,
+    })
+}
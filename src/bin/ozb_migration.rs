@@ -3,6 +3,7 @@ use mongodb::{
     options::{ClientOptions, ServerApi, ServerApiVersion},
     Client,
 };
+use ozb::util::redact_connection_string;
 use sqlx::postgres::PgPoolOptions;
 use tracing::Level;
 use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
@@ -25,13 +26,19 @@ async fn main() -> Result<(), anyhow::Error> {
     let database_url = std::env::var("DATABASE_URL")?;
     let mongodb_connection_string = std::env::var("MONGODB_CONNECTION_STRING")?;
 
-    tracing::info!("connecting to mongodb: {mongodb_connection_string}");
+    tracing::info!(
+        "connecting to mongodb: {}",
+        redact_connection_string(&mongodb_connection_string)
+    );
     let mut client_options = ClientOptions::parse(mongodb_connection_string).await?;
     let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
     client_options.server_api = Some(server_api);
     let client = Client::with_options(client_options)?;
 
-    tracing::info!("connecting to db: {database_url}");
+    tracing::info!(
+        "connecting to db: {}",
+        redact_connection_string(&database_url)
+    );
     let pool = PgPoolOptions::new()
         .max_connections(1)
         .connect(&database_url)
@@ -81,7 +88,9 @@ async fn main() -> Result<(), anyhow::Error> {
         .await?;
 
         let discord_notification_id = sqlx::query!(
-            "INSERT INTO discord_notifications (channel_id) VALUES ($1) RETURNING id",
+            r#"INSERT INTO discord_notifications (channel_id) VALUES ($1)
+                ON CONFLICT (type, channel_id) DO UPDATE SET channel_id = EXCLUDED.channel_id
+                RETURNING id"#,
             registered_keyword.channel_id
         )
         .fetch_one(&mut *transaction)
@@ -1,16 +1,100 @@
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
-use std::{sync::Arc, time::Duration};
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use ozb::{
+    matcher::KeywordMatcher,
+    notification::{DealNotification, NotificationAction, NotificationFormat},
+    ozbargain::{description_text, format_price, merchant, price_value, DEALS_SOURCE},
+    util::redact_connection_string,
+};
+use sqlx::{postgres::PgPoolOptions, PgConnection, Pool, Postgres};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tracing::Level;
 use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
 use twilight_http::Client as DiscordHttpClient;
 use twilight_model::{
-    channel::message::AllowedMentions,
+    channel::message::{
+        component::{ActionRow, Button, ButtonStyle},
+        AllowedMentions, Component,
+    },
     id::{marker::ChannelMarker, Id},
 };
-use twilight_util::builder::embed::{EmbedBuilder, EmbedFieldBuilder, ImageSource};
 
 type State = state::TypeMap![Send + Sync];
+
+/// Backlog size on start that suggests catch-up mode was forgotten
+const LARGE_BACKLOG: i64 = 200;
+/// Attempts before a post is left failed
+const MAX_ATTEMPTS: i32 = 5;
+/// How long a trigger owns the posts it claimed, renewed while sending
+const LEASE: Duration = Duration::from_secs(5 * 60);
+/// How often the lease is renewed, well before it runs out
+const LEASE_RENEWAL: Duration = Duration::from_secs(60);
+/// Delay before retrying a failed post, multiplied by its attempts
+const RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Settings read from the environment on start
+struct TriggerConfig {
+    /// Posts claimed at a time, batches are processed until the backlog is empty
+    batch_size: i64,
+    /// Older posts are skipped instead of notified
+    max_post_age: Duration,
+    /// Skip the backlog stored before the trigger started, e.g. on the first run or after a restore
+    catch_up: bool,
+    /// Skip the backlog stored before this time, in the database's time zone, unlike
+    /// `catch_up` it can stay set across restarts
+    catch_up_before: Option<NaiveDateTime>,
+}
+
+impl TriggerConfig {
+    fn from_env() -> Result<Self, anyhow::Error> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+        Ok(Self {
+            batch_size: var("TRIGGER_BATCH_SIZE")
+                .map(|v| v.parse())
+                .transpose()
+                .context("TRIGGER_BATCH_SIZE must be a number")?
+                .unwrap_or(50)
+                .max(1),
+            max_post_age: Duration::from_secs(
+                var("TRIGGER_MAX_POST_AGE_HOURS")
+                    .map(|v| v.parse::<u64>())
+                    .transpose()
+                    .context("TRIGGER_MAX_POST_AGE_HOURS must be a number of hours")?
+                    .unwrap_or(24)
+                    * 60
+                    * 60,
+            ),
+            catch_up: var("TRIGGER_CATCH_UP")
+                .map(|v| v.parse())
+                .transpose()
+                .context("TRIGGER_CATCH_UP must be true or false")?
+                .unwrap_or(false),
+            catch_up_before: var("TRIGGER_CATCH_UP_BEFORE")
+                .map(|v| v.parse())
+                .transpose()
+                .context("TRIGGER_CATCH_UP_BEFORE must be a time, e.g. 2026-10-19T09:00:00")?,
+        })
+    }
+}
+
+/// Matcher built for the registered keywords, kept between batches
+struct MatcherCache {
+    registrations: Vec<(i32, String)>,
+    matcher: Arc<KeywordMatcher>,
+}
+
 struct MatchedDetails {
+    keyword_id: i32,
+    keyword: String,
+    discord_user_id: i32,
+    notification_format: String,
+    notification_template: Option<String>,
+    note: Option<String>,
     title: String,
     post_id: i32,
     link: String,
@@ -18,182 +102,730 @@ struct MatchedDetails {
     discord_id: String,
     categories: Vec<String>,
     thumbnail: Option<String>,
+    has_merchant: bool,
+    /// plain text, see [`description_text`]
+    description: String,
+    publication_date: NaiveDateTime,
+    ozbargain_id: String,
+    votes: Option<(i32, i32)>,
+    coupon_codes: Vec<String>,
 }
 
-async fn process_message(state: Arc<State>) -> Result<(), anyhow::Error> {
+/// Processes one batch of posts, returns how many were claimed
+async fn process_message(state: Arc<State>) -> Result<usize, anyhow::Error> {
     tracing::info!("processing message");
     let pool = state.get::<Pool<Postgres>>();
-    let mut transaction = pool.begin().await?;
     let discord_http_client = state.get::<DiscordHttpClient>();
+    let config = state.get::<TriggerConfig>();
+
+    let skipped = skip_stale_posts(&mut *pool.acquire().await?, config.max_post_age, None).await?;
+    if skipped > 0 {
+        tracing::warn!(
+            "skipped {skipped} posts older than {:?}",
+            config.max_post_age
+        );
+    }
 
-    let posts = sqlx::query!(
-        r#"SELECT * from ozbargain_posts WHERE notified = false LIMIT 10 FOR UPDATE SKIP LOCKED"#,
+    let expired = sqlx::query!(
+        r#"UPDATE ozbargain_posts SET processing_state = 'failed', last_error = 'lease expired'
+            WHERE processing_state = 'processing' AND leased_until < now() AND attempts >= $1"#,
+        MAX_ATTEMPTS
     )
-    .fetch_all(&mut *transaction)
+    .execute(pool)
     .await?;
 
-    tracing::info!("processing: {}", posts.len());
+    if expired.rows_affected() > 0 {
+        tracing::warn!(
+            "gave up on {} posts that never finished processing",
+            expired.rows_affected()
+        );
+    }
 
-    let ids = posts.iter().map(|p| p.id).collect::<Vec<_>>();
-    // notified means processed..
-    sqlx::query!(
-        "UPDATE ozbargain_posts SET notified = true WHERE id = ANY($1)",
+    // claimed posts are leased, if this trigger dies they're picked up again once it expires
+    let lease_id = uuid::Uuid::new_v4().to_string();
+    let ids = sqlx::query!(
+        r#"UPDATE ozbargain_posts
+            SET processing_state = 'processing', attempts = attempts + 1,
+                leased_until = now() + make_interval(secs => $1), leased_by = $4
+            WHERE id IN (
+                SELECT id FROM ozbargain_posts
+                WHERE processing_state = 'pending'
+                    OR (processing_state IN ('processing', 'failed')
+                        AND leased_until < now() AND attempts < $2)
+                ORDER BY id LIMIT $3 FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id"#,
+        LEASE.as_secs_f64(),
+        MAX_ATTEMPTS,
+        config.batch_size,
+        lease_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|p| p.id)
+    .collect::<Vec<_>>();
+
+    tracing::info!("processing: {}", ids.len());
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    let posts = sqlx::query!(
+        r#"SELECT op.*, ARRAY(
+                SELECT fs.name FROM post_feed_sources AS pfs
+                JOIN feed_sources AS fs ON pfs.feed_source_id = fs.id
+                WHERE pfs.post_id = op.id
+            ) AS "sources!" from ozbargain_posts AS op
+            WHERE op.id = ANY($1) ORDER BY op.id"#,
         &ids
     )
-    .execute(&mut *transaction)
+    .fetch_all(pool)
     .await?;
 
+    // a retried post skips the notifications that went out on an earlier attempt
+    let already_sent = sqlx::query!(
+        r#"SELECT post_id AS "post_id!", keyword_id AS "keyword_id!" FROM audit
+            WHERE post_id = ANY($1) AND keyword_id IS NOT NULL"#,
+        &ids
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|a| (a.post_id, a.keyword_id))
+    .collect::<HashSet<_>>();
+
     let current_keywords = sqlx::query!(
         r#"
                 SELECT rk.*, du.discord_id, dn.channel_id FROM registered_keywords as rk
                 JOIN discord_users AS du on rk.discord_user_id = du.id
                 JOIN discord_notifications AS dn on rk.discord_notification_id = dn.id
+                WHERE rk.snoozed_until IS NULL OR rk.snoozed_until < now()
+                ORDER BY rk.id
                 "#
     )
-    .fetch_all(&mut *transaction)
+    .fetch_all(pool)
     .await?;
 
+    let muted_merchants = sqlx::query!("SELECT discord_user_id, merchant FROM muted_merchants")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|m| (m.discord_user_id, m.merchant.to_lowercase()))
+        .collect::<HashSet<_>>();
+
+    // keywords already notified for the post a re-post was linked to
+    let reposted_from = posts
+        .iter()
+        .filter_map(|p| p.reposted_from)
+        .collect::<Vec<_>>();
+    let previously_notified = sqlx::query!(
+        r#"SELECT DISTINCT post_id AS "post_id!", keyword_id AS "keyword_id!" FROM audit
+            WHERE post_id = ANY($1) AND keyword_id IS NOT NULL"#,
+        &reposted_from
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .fold(HashMap::<i32, Vec<i32>>::new(), |mut notified, a| {
+        notified.entry(a.post_id).or_default().push(a.keyword_id);
+        notified
+    });
+
+    let mut post_categories = HashMap::<i32, Vec<String>>::new();
+    for category in sqlx::query!(
+        r#"SELECT ca.post_id AS "post_id!", c.name FROM category_association AS ca
+            JOIN categories AS c ON ca.category_id = c.id WHERE ca.post_id = ANY($1)"#,
+        &ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        post_categories
+            .entry(category.post_id)
+            .or_default()
+            .push(category.name);
+    }
+
+    // the matcher is indexed like current_keywords, so it's rebuilt whenever they change
+    let registrations = current_keywords
+        .iter()
+        .map(|k| (k.id, k.keyword.clone()))
+        .collect::<Vec<_>>();
+    let keyword_indices = current_keywords
+        .iter()
+        .enumerate()
+        .map(|(index, k)| (k.id, index))
+        .collect::<HashMap<_, _>>();
+    let matcher = {
+        let mut cache = state
+            .get::<Mutex<Option<MatcherCache>>>()
+            .lock()
+            .map_err(|_| anyhow::anyhow!("matcher cache poisoned"))?;
+
+        match cache.as_ref() {
+            Some(cached) if cached.registrations == registrations => cached.matcher.clone(),
+            _ => {
+                tracing::info!("building matcher for {} keywords", registrations.len());
+                let matcher = Arc::new(KeywordMatcher::new(
+                    registrations.iter().map(|(_, keyword)| keyword.as_str()),
+                )?);
+                *cache = Some(MatcherCache {
+                    registrations,
+                    matcher: matcher.clone(),
+                });
+
+                matcher
+            }
+        }
+    };
+
     let mut matched_keywords = vec![];
     for ozbargain_post in posts {
-        for keyword_data in &current_keywords {
-            let keyword = keyword_data.keyword.to_ascii_lowercase();
-            let keyword_categories = &keyword_data.categories;
-
-            // dbg!(&keyword);
-            // dbg!(&keyword_data);
-            // dbg!(&keyword_categories);
-
-            let does_match = {
-                let title = &ozbargain_post.title;
-                let description = &ozbargain_post.description;
-
-                let post_categories = sqlx::query!(
-                    r#"SELECT name FROM category_association as ca
-                            JOIN categories as c on ca.category_id = c.id WHERE post_id = $1"#,
-                    ozbargain_post.id
-                )
-                .fetch_all(pool)
-                .await?
+        let post_merchant = merchant(&ozbargain_post.title).map(|m| m.to_lowercase());
+        let mut sources = ozbargain_post.sources.clone();
+        if sources.is_empty() {
+            sources.push(DEALS_SOURCE.to_owned());
+        }
+        let categories = post_categories
+            .remove(&ozbargain_post.id)
+            .unwrap_or_default();
+        // posts stored before description_text was added only have the html
+        let description = ozbargain_post
+            .description_text
+            .clone()
+            .unwrap_or_else(|| description_text(&ozbargain_post.description));
+        let keyword_matches = matcher.matches(&[&ozbargain_post.title, &description]);
+        // re-posts also go to keywords notified for the earlier post, whether they match or not
+        let reposted_keywords = ozbargain_post
+            .reposted_from
+            .and_then(|id| previously_notified.get(&id))
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| keyword_indices.get(id).copied())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let mut candidates = [keyword_matches.as_slice(), reposted_keywords.as_slice()].concat();
+        candidates.sort_unstable();
+        candidates.dedup();
+        for index in candidates {
+            let keyword_data = &current_keywords[index];
+            let title_or_description = keyword_matches.binary_search(&index).is_ok();
+            let reposted = reposted_keywords.contains(&index);
+
+            if !keyword_data
+                .feed_sources
                 .iter()
-                .map(|pc| pc.name.clone())
-                .collect::<Vec<_>>();
-
-                let description = tl::parse(description, tl::ParserOptions::default())
-                    .map(|dom| {
-                        let mut string_list = Vec::new();
-                        for node in dom.nodes() {
-                            if let Some(tag) = node.as_tag() {
-                                if tag.name() == "img" {
-                                    string_list.push(
-                                        tag.attributes()
-                                            .get("alt")
-                                            .unwrap_or(None)
-                                            .unwrap_or(&tl::Bytes::from(""))
-                                            .as_utf8_str()
-                                            .to_string(),
-                                    )
-                                }
-                            };
-
-                            string_list.push(node.inner_text(dom.parser()).to_string())
-                        }
-
-                        string_list.join("\n")
-                    })
-                    .unwrap_or(description.to_owned());
-
-                let title_or_description = title.to_ascii_lowercase().contains(&keyword)
-                    || description.to_ascii_lowercase().contains(&keyword);
-
-                let category_matches = keyword_categories.is_empty()
-                    || keyword_categories.iter().any(|c| *c == "All")
-                    || keyword_categories
-                        .iter()
-                        .any(|p| post_categories.iter().any(|c| p == c));
-
-                (
-                    title_or_description && category_matches,
-                    MatchedDetails {
-                        title: ozbargain_post.title.clone(),
-                        link: ozbargain_post.link.clone(),
-                        post_id: ozbargain_post.id,
-                        categories: post_categories,
-                        thumbnail: ozbargain_post.thumbnail.clone(),
-                        channel_id: keyword_data.channel_id.clone(),
-                        discord_id: keyword_data.discord_id.clone(),
-                    },
-                )
-            };
+                .any(|s| sources.contains(s))
+                || already_sent.contains(&(ozbargain_post.id, keyword_data.id))
+            {
+                continue;
+            }
+
+            if post_merchant.as_ref().is_some_and(|m| {
+                muted_merchants.contains(&(keyword_data.discord_user_id, m.to_owned()))
+            }) {
+                continue;
+            }
+
+            let category_matches = keyword_data.categories.is_empty()
+                || keyword_data.categories.iter().any(|c| c == "All")
+                || keyword_data
+                    .categories
+                    .iter()
+                    .any(|p| categories.iter().any(|c| p == c));
 
-            if does_match.0 {
-                matched_keywords.push((keyword_data, does_match.1));
+            if !((title_or_description && category_matches) || (reposted && keyword_data.renotify))
+            {
+                continue;
             }
+
+            matched_keywords.push(MatchedDetails {
+                keyword_id: keyword_data.id,
+                keyword: keyword_data.keyword.clone(),
+                discord_user_id: keyword_data.discord_user_id,
+                notification_format: keyword_data.notification_format.clone(),
+                notification_template: keyword_data.notification_template.clone(),
+                note: reposted.then(|| "Re-posted deal".to_owned()),
+                title: ozbargain_post.title.clone(),
+                link: ozbargain_post.link.clone(),
+                post_id: ozbargain_post.id,
+                categories: categories.clone(),
+                thumbnail: ozbargain_post.thumbnail.clone(),
+                has_merchant: post_merchant.is_some(),
+                description: description.clone(),
+                publication_date: ozbargain_post.publication_date,
+                ozbargain_id: ozbargain_post.ozbargain_id.clone(),
+                votes: ozbargain_post
+                    .votes_positive
+                    .zip(ozbargain_post.votes_negative),
+                coupon_codes: ozbargain_post.coupon_codes.clone(),
+                channel_id: keyword_data.channel_id.clone(),
+                discord_id: keyword_data.discord_id.clone(),
+            });
         }
     }
     tracing::info!("matched {}", matched_keywords.len());
+    let mut conn = pool.acquire().await?;
+    let mut failures = HashMap::<i32, String>::new();
+    // posts another trigger claimed after the lease ran out, it sends them instead
+    let mut lost = HashSet::<i32>::new();
+    let mut renewed_at = Instant::now();
     for matched in matched_keywords {
-        let title = matched.1.title;
-        let link = matched.1.link;
-        let keyword = &matched.0.keyword;
-        let post_categories = matched.1.categories;
-        let thumbnail = matched.1.thumbnail;
-        // FIXME: type should be prefix
-        let user_id = matched.1.discord_id;
-        // FIXME: could be webhook
-        let channel_id = matched.1.channel_id;
-
-        let embed = EmbedBuilder::default()
-            .color(0xde935f)
-            .title("OzBargain")
-            .field(EmbedFieldBuilder::new("Title", title))
-            .field(EmbedFieldBuilder::new("Link", link))
-            .field(EmbedFieldBuilder::new("Keyword", keyword))
-            .field(EmbedFieldBuilder::new(
-                "Categories",
-                post_categories.join(", "),
-            ));
-
-        let embed = if let Some(ref thumbnail) = thumbnail {
-            embed.thumbnail(ImageSource::url(thumbnail)?)
-        } else {
-            embed
-        };
+        let post_id = matched.post_id;
+        // discord rate limits can keep a batch sending for longer than the lease
+        if renewed_at.elapsed() >= LEASE_RENEWAL {
+            let kept = renew_lease(&mut conn, &ids, &lease_id).await?;
+            lost.extend(ids.iter().filter(|id| !kept.contains(id)));
+            renewed_at = Instant::now();
+        }
+
+        if lost.contains(&post_id) {
+            continue;
+        }
+
+        if let Err(e) = send_notification(discord_http_client, &mut conn, matched).await {
+            tracing::error!("error sending notif for post {post_id}: {e}");
+            failures.entry(post_id).or_insert_with(|| e.to_string());
+        }
+    }
+
+    if !lost.is_empty() {
+        tracing::warn!("lost the lease on {} posts while sending", lost.len());
+    }
+
+    let done = ids
+        .iter()
+        .filter(|id| !failures.contains_key(id) && !lost.contains(id))
+        .copied()
+        .collect::<Vec<_>>();
+    sqlx::query!(
+        r#"UPDATE ozbargain_posts SET processing_state = 'done', leased_until = NULL, last_error = NULL
+            WHERE id = ANY($1) AND leased_by = $2"#,
+        &done,
+        lease_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let (failed, errors): (Vec<_>, Vec<_>) = failures.into_iter().unzip();
+    // retried later with the notifications that failed, backing off with each attempt
+    sqlx::query!(
+        r#"UPDATE ozbargain_posts AS op
+            SET processing_state = 'failed', last_error = f.error,
+                leased_until = now() + make_interval(secs => $3::FLOAT8 * op.attempts)
+            FROM UNNEST($1::INTEGER[], $2::TEXT[]) AS f(id, error)
+            WHERE op.id = f.id AND op.leased_by = $4"#,
+        &failed,
+        &errors,
+        RETRY_DELAY.as_secs_f64(),
+        lease_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if !failed.is_empty() {
+        tracing::warn!("{} posts failed and will be retried", failed.len());
+    }
+
+    Ok(ids.len())
+}
+
+/// Skips unprocessed posts older than `max_post_age`, and any stored before `stored_before`
+async fn skip_stale_posts(
+    conn: &mut PgConnection,
+    max_post_age: Duration,
+    stored_before: Option<NaiveDateTime>,
+) -> Result<u64, anyhow::Error> {
+    // publication dates are stored as utc, posts requeued for a feed they entered later,
+    // e.g. deals that became popular, are as old as when they entered it
+    Ok(sqlx::query!(
+        r#"UPDATE ozbargain_posts AS op SET processing_state = 'skipped', leased_until = NULL
+            WHERE op.processing_state IN ('pending', 'failed')
+                AND (op.created_at < $2 OR (
+                    op.publication_date < (now() AT TIME ZONE 'UTC') - make_interval(secs => $1)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM post_feed_sources AS pfs
+                        WHERE pfs.post_id = op.id AND pfs.created_at > op.created_at
+                            AND pfs.created_at >= now() - make_interval(secs => $1)
+                    )
+                ))"#,
+        max_post_age.as_secs_f64(),
+        stored_before
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected())
+}
+
+/// Extends the lease on the posts the batch still owns and returns them
+async fn renew_lease(
+    conn: &mut PgConnection,
+    ids: &[i32],
+    lease_id: &str,
+) -> Result<HashSet<i32>, anyhow::Error> {
+    Ok(sqlx::query!(
+        r#"UPDATE ozbargain_posts SET leased_until = now() + make_interval(secs => $3)
+            WHERE id = ANY($1) AND leased_by = $2 AND processing_state = 'processing'
+            RETURNING id"#,
+        ids,
+        lease_id,
+        LEASE.as_secs_f64()
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|p| p.id)
+    .collect())
+}
 
-        let allowed_mentions = AllowedMentions {
-            parse: vec![],
-            users: Vec::from([Id::new(user_id.parse()?)]),
-            roles: vec![],
-            replied_user: false,
+/// Re-notifies opted in keywords when a deal they were notified about drops in price
+async fn process_revisions(state: Arc<State>) -> Result<(), anyhow::Error> {
+    let pool = state.get::<Pool<Postgres>>();
+    let discord_http_client = state.get::<DiscordHttpClient>();
+
+    // marked processed before sending, a missed price drop isn't worth a duplicate ping
+    let mut revisions = sqlx::query!(
+        r#"UPDATE ozbargain_post_revisions SET processed = true
+            WHERE id IN (
+                SELECT id FROM ozbargain_post_revisions
+                WHERE processed = false ORDER BY id LIMIT 50 FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, post_id, title"#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if revisions.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("processing revisions: {}", revisions.len());
+
+    // the oldest pending revision has the price before this batch of changes,
+    // used for keywords notified before the notified price was recorded
+    revisions.sort_unstable_by_key(|r| r.id);
+    let mut previous_titles = HashMap::new();
+    for revision in revisions {
+        previous_titles
+            .entry(revision.post_id)
+            .or_insert(revision.title);
+    }
+
+    let post_ids = previous_titles.keys().copied().collect::<Vec<_>>();
+    let posts = sqlx::query!(
+        "SELECT * FROM ozbargain_posts WHERE id = ANY($1) ORDER BY id",
+        &post_ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut post_categories = HashMap::<i32, Vec<String>>::new();
+    for category in sqlx::query!(
+        r#"SELECT ca.post_id AS "post_id!", c.name FROM category_association AS ca
+            JOIN categories AS c ON ca.category_id = c.id WHERE ca.post_id = ANY($1)"#,
+        &post_ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        post_categories
+            .entry(category.post_id)
+            .or_default()
+            .push(category.name);
+    }
+
+    // the latest notification for each keyword has the price it last saw
+    let mut post_keywords = HashMap::<i32, Vec<_>>::new();
+    for keyword in sqlx::query!(
+        r#"SELECT DISTINCT ON (a.post_id, rk.id) a.post_id AS "post_id!", rk.*, du.discord_id,
+                dn.channel_id, a.price AS notified_price
+            FROM audit AS a
+            JOIN registered_keywords AS rk ON a.keyword_id = rk.id
+            JOIN discord_users AS du on rk.discord_user_id = du.id
+            JOIN discord_notifications AS dn on rk.discord_notification_id = dn.id
+            WHERE a.post_id = ANY($1) AND rk.renotify
+                AND (rk.snoozed_until IS NULL OR rk.snoozed_until < now())
+            ORDER BY a.post_id, rk.id, a.created_at DESC"#,
+        &post_ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        post_keywords
+            .entry(keyword.post_id)
+            .or_default()
+            .push(keyword);
+    }
+
+    let muted_merchants = sqlx::query!("SELECT discord_user_id, merchant FROM muted_merchants")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|m| (m.discord_user_id, m.merchant.to_lowercase()))
+        .collect::<HashSet<_>>();
+
+    let mut matched_keywords = vec![];
+    for post in posts {
+        let Some(current_price) = price_value(&post.title) else {
+            continue;
         };
+        let previous_price = previous_titles
+            .get(&post.id)
+            .and_then(|title| price_value(title));
+        let categories = post_categories.remove(&post.id).unwrap_or_default();
+        let post_merchant = merchant(&post.title).map(|m| m.to_lowercase());
+        let description = post
+            .description_text
+            .clone()
+            .unwrap_or_else(|| description_text(&post.description));
+
+        for keyword_data in post_keywords.remove(&post.id).unwrap_or_default() {
+            let Some(notified_price) = keyword_data.notified_price.or(previous_price) else {
+                continue;
+            };
+
+            if current_price >= notified_price {
+                continue;
+            }
+
+            if post_merchant.as_ref().is_some_and(|m| {
+                muted_merchants.contains(&(keyword_data.discord_user_id, m.to_owned()))
+            }) {
+                continue;
+            }
+
+            let note = format!("Price dropped from {}", format_price(notified_price));
+            tracing::info!(
+                "price drop {} for keyword {}: {note}",
+                post.ozbargain_id,
+                keyword_data.id
+            );
+
+            matched_keywords.push(MatchedDetails {
+                keyword_id: keyword_data.id,
+                keyword: keyword_data.keyword,
+                discord_user_id: keyword_data.discord_user_id,
+                notification_format: keyword_data.notification_format,
+                notification_template: keyword_data.notification_template,
+                note: Some(note),
+                title: post.title.clone(),
+                post_id: post.id,
+                link: post.link.clone(),
+                channel_id: keyword_data.channel_id,
+                discord_id: keyword_data.discord_id,
+                categories: categories.clone(),
+                thumbnail: post.thumbnail.clone(),
+                has_merchant: post_merchant.is_some(),
+                description: description.clone(),
+                publication_date: post.publication_date,
+                ozbargain_id: post.ozbargain_id.clone(),
+                votes: post.votes_positive.zip(post.votes_negative),
+                coupon_codes: post.coupon_codes.clone(),
+            });
+        }
+    }
 
-        if let Err(e) = discord_http_client
-            .create_message(Id::<ChannelMarker>::new(channel_id.parse()?))
-            .embeds(&[embed.build()])
-            .allowed_mentions(Some(&allowed_mentions))
-            .content(&format!("<@{}>", user_id))
-            .await
-        {
-            tracing::error!("error sending notif: {e}");
+    let mut conn = pool.acquire().await?;
+    for matched in matched_keywords {
+        if let Err(e) = send_notification(discord_http_client, &mut conn, matched).await {
+            tracing::error!("error sending price drop notif: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_notification(
+    discord_http_client: &DiscordHttpClient,
+    conn: &mut PgConnection,
+    matched: MatchedDetails,
+) -> Result<(), anyhow::Error> {
+    // FIXME: type should be prefix
+    let user_id = matched.discord_id;
+    // FIXME: could be webhook
+    let channel_id = matched.channel_id;
+    let price = price_value(&matched.title);
+
+    let notification = DealNotification {
+        title: matched.title,
+        link: matched.link,
+        description: matched.description,
+        keyword: matched.keyword,
+        categories: matched.categories,
+        thumbnail: matched.thumbnail,
+        publication_date: matched.publication_date,
+        ozbargain_id: matched.ozbargain_id,
+        votes: matched.votes,
+        coupon_codes: matched.coupon_codes,
+        note: matched.note,
+    };
+
+    let mention = format!("<@{}>", user_id);
+    let format = matched
+        .notification_format
+        .parse::<NotificationFormat>()
+        .unwrap_or_default();
+    let rendered =
+        match notification.render(&mention, format, matched.notification_template.as_deref()) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                tracing::warn!("error rendering notification, using default format: {e}");
+                notification.render(&mention, NotificationFormat::default(), None)?
+            }
         };
 
-        tracing::info!("discord: notification sent {} {}", channel_id, user_id);
-
-        sqlx::query!(
-            r#"INSERT INTO audit
-                            (post_id, keyword_id, user_id)
-                            VALUES
-                            ($1, $2, $3)"#,
-            matched.1.post_id,
-            matched.0.id,
-            matched.0.discord_user_id
-        )
-        .execute(&mut *transaction)
-        .await?;
+    let mut buttons = vec![
+        Component::Button(Button {
+            custom_id: Some(
+                NotificationAction::Unregister {
+                    keyword_id: matched.keyword_id,
+                }
+                .custom_id(),
+            ),
+            disabled: false,
+            emoji: None,
+            label: Some("Unregister keyword".to_owned()),
+            style: ButtonStyle::Danger,
+            url: None,
+            sku_id: None,
+        }),
+        Component::Button(Button {
+            custom_id: Some(
+                NotificationAction::Snooze {
+                    keyword_id: matched.keyword_id,
+                }
+                .custom_id(),
+            ),
+            disabled: false,
+            emoji: None,
+            label: Some("Snooze 24h".to_owned()),
+            style: ButtonStyle::Secondary,
+            url: None,
+            sku_id: None,
+        }),
+    ];
+
+    if matched.has_merchant {
+        buttons.push(Component::Button(Button {
+            custom_id: Some(
+                NotificationAction::MuteMerchant {
+                    post_id: matched.post_id,
+                }
+                .custom_id(),
+            ),
+            disabled: false,
+            emoji: None,
+            label: Some("Not interested in merchant".to_owned()),
+            style: ButtonStyle::Secondary,
+            url: None,
+            sku_id: None,
+        }));
     }
 
+    let action_row = Component::ActionRow(ActionRow {
+        components: buttons,
+    });
+
+    let allowed_mentions = AllowedMentions {
+        parse: vec![],
+        users: Vec::from([Id::new(user_id.parse()?)]),
+        roles: vec![],
+        replied_user: false,
+    };
+
+    // not recorded in the audit when sending fails, so the post's retry sends it again
+    let response = discord_http_client
+        .create_message(Id::<ChannelMarker>::new(channel_id.parse()?))
+        .embeds(rendered.embed.as_slice())
+        .allowed_mentions(Some(&allowed_mentions))
+        .components(&[action_row])
+        .content(&rendered.content)
+        .await
+        .context("error sending notif")?;
+
+    let message_id = match response.model().await {
+        Ok(message) => Some(message.id.to_string()),
+        Err(e) => {
+            tracing::error!("error reading sent notif: {e}");
+            None
+        }
+    };
+
+    tracing::info!("discord: notification sent {} {}", channel_id, user_id);
+
+    sqlx::query!(
+        r#"INSERT INTO audit
+                        (post_id, keyword_id, user_id, channel_id, message_id, price)
+                        VALUES
+                        ($1, $2, $3, $4, $5, $6)"#,
+        matched.post_id,
+        matched.keyword_id,
+        matched.discord_user_id,
+        channel_id,
+        message_id,
+        price
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks the backlog stored before the cutoff processed without notifying when catch-up
+/// mode is on, posts older than the max post age are skipped by every batch anyway.
+async fn catch_up(pool: &Pool<Postgres>, config: &TriggerConfig) -> Result<(), anyhow::Error> {
+    let backlog = sqlx::query!(
+        r#"SELECT count(*) AS "count!", now()::TIMESTAMP AS "started!",
+                min(publication_date) AS oldest, max(publication_date) AS newest
+            FROM ozbargain_posts WHERE processing_state IN ('pending', 'failed')"#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let stored_before = match config.catch_up_before {
+        Some(catch_up_before) => catch_up_before,
+        None if config.catch_up => backlog.started,
+        None => {
+            if backlog.count > LARGE_BACKLOG {
+                tracing::warn!(
+                    posts = backlog.count,
+                    oldest = ?backlog.oldest,
+                    "large backlog, posts newer than {:?} will be notified, consider TRIGGER_CATCH_UP",
+                    config.max_post_age
+                );
+            }
+
+            return Ok(());
+        }
+    };
+
+    let mut transaction = pool.begin().await?;
+    let posts =
+        skip_stale_posts(&mut transaction, config.max_post_age, Some(stored_before)).await?;
+
+    let revisions = sqlx::query!(
+        r#"UPDATE ozbargain_post_revisions SET processed = true
+            WHERE processed = false AND created_at < $1"#,
+        stored_before
+    )
+    .execute(&mut *transaction)
+    .await?;
+
     transaction.commit().await?;
 
-    Ok::<(), anyhow::Error>(())
+    tracing::warn!(
+        posts,
+        revisions = revisions.rows_affected(),
+        backlog = backlog.count,
+        oldest = ?backlog.oldest,
+        newest = ?backlog.newest,
+        "catch-up mode: marked backlog stored before {stored_before} processed without notifying"
+    );
+
+    Ok(())
 }
 
 #[tokio::main]
@@ -205,23 +837,43 @@ async fn main() -> Result<(), anyhow::Error> {
 
     let database_url = std::env::var("DATABASE_URL")?;
     let discord_token = std::env::var("DISCORD_TOKEN")?;
+    let config = TriggerConfig::from_env()?;
+    let batch_size = config.batch_size;
 
     let discord_http_client = DiscordHttpClient::new(discord_token);
 
-    tracing::info!("connecting to db: {database_url}");
+    tracing::info!(
+        "connecting to db: {}",
+        redact_connection_string(&database_url)
+    );
     let pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(&database_url)
         .await?;
 
     sqlx::migrate!("./migrations").run(&pool).await?;
+    catch_up(&pool, &config).await?;
 
     let state = Arc::new(State::default());
     state.set(pool);
     state.set(discord_http_client);
+    state.set(Mutex::<Option<MatcherCache>>::new(None));
+    state.set(config);
 
     loop {
-        if let Err(e) = process_message(state.clone()).await {
+        // a short batch means the backlog is drained
+        loop {
+            match process_message(state.clone()).await {
+                Ok(claimed) if claimed as i64 >= batch_size => continue,
+                Ok(_) => break,
+                Err(e) => {
+                    tracing::error!("{e}");
+                    break;
+                }
+            }
+        }
+
+        if let Err(e) = process_revisions(state.clone()).await {
             tracing::error!("{e}")
         }
 
@@ -0,0 +1,429 @@
+use crate::{skip_option, skip_result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::{collections::BTreeMap, str::FromStr};
+
+/// How a source's feed is parsed, see the `feed_sources` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeedAdapter {
+    /// OzBargain RSS, with votes and thumbnails from its extensions
+    #[default]
+    OzBargain,
+    Rss,
+    Atom,
+}
+
+impl FromStr for FeedAdapter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ozbargain" => Ok(Self::OzBargain),
+            "rss" => Ok(Self::Rss),
+            "atom" => Ok(Self::Atom),
+            _ => anyhow::bail!("unknown feed adapter: {s}"),
+        }
+    }
+}
+
+/// Where the generic adapters read each field from, `None` uses the default.
+#[derive(Debug, Clone, Default)]
+pub struct FieldMapping {
+    /// `guid` (rss default), `id` (atom default) or `link`
+    pub guid: Option<String>,
+    /// `category` (default) or an extension such as `dc:subject`
+    pub categories: Option<String>,
+    /// `enclosure` or an extension with a url attribute such as `media:thumbnail` (default)
+    pub thumbnail: Option<String>,
+}
+
+/// Feed entry normalised across adapters.
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    pub guid: String,
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub thumbnail: Option<String>,
+    pub publication_date: NaiveDateTime,
+    pub categories: Vec<String>,
+    pub votes: Option<(i32, i32)>,
+    /// Where the deal itself lives, used to link re-posts of the same deal
+    pub deal_url: Option<String>,
+}
+
+pub fn parse_feed(
+    adapter: FeedAdapter,
+    mapping: &FieldMapping,
+    body: &[u8],
+) -> Result<Vec<FeedItem>, anyhow::Error> {
+    match adapter {
+        FeedAdapter::OzBargain => parse_ozbargain(body),
+        FeedAdapter::Rss => parse_rss(mapping, body),
+        FeedAdapter::Atom => parse_atom(mapping, body),
+    }
+}
+
+fn parse_ozbargain(body: &[u8]) -> Result<Vec<FeedItem>, anyhow::Error> {
+    let channel = rss::Channel::read_from(body)?;
+    let mut items = Vec::with_capacity(channel.items().len());
+
+    for item in channel.items() {
+        let guid = skip_option!(item.guid(), "guid")
+            .value()
+            .split_ascii_whitespace()
+            .next();
+
+        let guid = skip_option!(guid, "guid whitespace").to_owned();
+        let title = skip_option!(item.title(), "title").to_owned();
+        // Sun, 26 Mar 2023 17:29:29 +1100
+        let publication_date = skip_result!(
+            DateTime::parse_from_str(
+                skip_option!(item.pub_date(), "publication date"),
+                "%a, %d %b %Y %T %z",
+            ),
+            "publication date"
+        )
+        .naive_utc();
+        let link = skip_option!(item.link(), "link").to_owned();
+        let description = skip_option!(item.description(), "description").to_owned();
+        let ext = item.extensions();
+        let thumbnail = extension(ext, "media:thumbnail")
+            .first()
+            .and_then(|e| e.attrs().get("url"))
+            .cloned();
+
+        let meta = extension(ext, "ozb:meta").first();
+        let votes = |attr: &str| -> Option<i32> { meta?.attrs().get(attr)?.parse().ok() };
+
+        let categories = item
+            .categories()
+            .iter()
+            .map(|c| c.name.to_owned().replace("&amp;", "&"))
+            .collect();
+
+        items.push(FeedItem {
+            guid,
+            title,
+            link,
+            description,
+            thumbnail,
+            publication_date,
+            categories,
+            votes: votes("votes-pos").zip(votes("votes-neg")),
+            deal_url: meta.and_then(|m| m.attrs().get("url")).cloned(),
+        });
+    }
+
+    Ok(items)
+}
+
+fn parse_rss(mapping: &FieldMapping, body: &[u8]) -> Result<Vec<FeedItem>, anyhow::Error> {
+    let channel = rss::Channel::read_from(body)?;
+    let mut items = Vec::with_capacity(channel.items().len());
+
+    for item in channel.items() {
+        let link = skip_option!(item.link(), "link").to_owned();
+        let guid = match mapping.guid.as_deref().unwrap_or("guid") {
+            "link" => link.clone(),
+            _ => skip_option!(item.guid(), "guid").value().to_owned(),
+        };
+
+        let title = skip_option!(item.title(), "title").to_owned();
+        let publication_date = match item.pub_date() {
+            Some(pub_date) => {
+                skip_result!(DateTime::parse_from_rfc2822(pub_date), "publication date").naive_utc()
+            }
+            None => Utc::now().naive_utc(),
+        };
+
+        let description = item
+            .content()
+            .or(item.description())
+            .unwrap_or_default()
+            .to_owned();
+
+        let ext = item.extensions();
+        let thumbnail = match mapping.thumbnail.as_deref().unwrap_or("media:thumbnail") {
+            "enclosure" => item
+                .enclosure()
+                .filter(|e| e.mime_type().starts_with("image/"))
+                .map(|e| e.url().to_owned()),
+            path => extension(ext, path)
+                .first()
+                .and_then(|e| e.attrs().get("url"))
+                .cloned(),
+        };
+
+        let categories = match mapping.categories.as_deref().unwrap_or("category") {
+            "category" => item.categories().iter().map(|c| c.name.clone()).collect(),
+            // the rss crate parses dublin core itself, it isn't in the extension map
+            "dc:subject" => item
+                .dublin_core_ext()
+                .map(|dc| dc.subjects().to_vec())
+                .unwrap_or_default(),
+            path => extension(ext, path)
+                .iter()
+                .filter_map(|e| e.value().map(|v| v.to_owned()))
+                .collect(),
+        };
+
+        items.push(FeedItem {
+            guid,
+            title,
+            deal_url: Some(link.clone()),
+            link,
+            description,
+            thumbnail,
+            publication_date,
+            categories,
+            votes: None,
+        });
+    }
+
+    Ok(items)
+}
+
+fn parse_atom(mapping: &FieldMapping, body: &[u8]) -> Result<Vec<FeedItem>, anyhow::Error> {
+    let feed = atom_syndication::Feed::read_from(body)?;
+    let mut items = Vec::with_capacity(feed.entries().len());
+
+    for entry in feed.entries() {
+        let link = skip_option!(
+            entry
+                .links()
+                .iter()
+                .find(|l| l.rel() == "alternate")
+                .or(entry.links().first()),
+            "link"
+        )
+        .href()
+        .to_owned();
+
+        let guid = match mapping.guid.as_deref().unwrap_or("id") {
+            "link" => link.clone(),
+            _ => entry.id().to_owned(),
+        };
+
+        let description = entry
+            .content()
+            .and_then(|c| c.value())
+            .or(entry.summary().map(|s| s.as_str()))
+            .unwrap_or_default()
+            .to_owned();
+
+        let ext = entry.extensions();
+        let thumbnail = extension(
+            ext,
+            mapping.thumbnail.as_deref().unwrap_or("media:thumbnail"),
+        )
+        .first()
+        .and_then(|e| e.attrs().get("url"))
+        .cloned();
+
+        let categories = match mapping.categories.as_deref().unwrap_or("category") {
+            "category" => entry
+                .categories()
+                .iter()
+                .map(|c| c.label().unwrap_or(c.term()).to_owned())
+                .collect(),
+            path => extension(ext, path)
+                .iter()
+                .filter_map(|e| e.value().map(|v| v.to_owned()))
+                .collect(),
+        };
+
+        items.push(FeedItem {
+            guid,
+            title: entry.title().as_str().to_owned(),
+            deal_url: Some(link.clone()),
+            link,
+            description,
+            thumbnail,
+            publication_date: entry.published().unwrap_or(entry.updated()).naive_utc(),
+            categories,
+            votes: None,
+        });
+    }
+
+    Ok(items)
+}
+
+/// Looks up a `prefix:name` extension, shared by rss and atom extension maps
+fn extension<'a, E>(
+    extensions: &'a BTreeMap<String, BTreeMap<String, Vec<E>>>,
+    path: &str,
+) -> &'a [E] {
+    path.split_once(':')
+        .and_then(|(prefix, name)| extensions.get(prefix)?.get(name))
+        .map(|e| e.as_slice())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSS_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:media="http://search.yahoo.com/mrss/">
+  <channel>
+    <title>Deals Blog</title>
+    <link>https://example.com</link>
+    <description>Deals</description>
+    <item>
+      <title>Cheap SSD</title>
+      <link>https://example.com/deals/ssd</link>
+      <description>Half price drives</description>
+      <guid isPermaLink="false">post-1</guid>
+      <pubDate>Sun, 26 Mar 2023 17:29:29 +1100</pubDate>
+      <category>Computing</category>
+      <dc:subject>Storage</dc:subject>
+      <enclosure url="https://example.com/ssd.jpg" length="100" type="image/jpeg"/>
+      <media:thumbnail url="https://example.com/ssd-thumb.jpg"/>
+    </item>
+    <item>
+      <title>Undated</title>
+      <link>https://example.com/deals/undated</link>
+      <guid>post-2</guid>
+      <enclosure url="https://example.com/episode.mp3" length="100" type="audio/mpeg"/>
+    </item>
+    <item>
+      <title>No link</title>
+      <guid>post-3</guid>
+    </item>
+  </channel>
+</rss>"#;
+
+    const ATOM_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">
+  <title>Deals</title>
+  <id>urn:example:deals</id>
+  <updated>2023-03-26T06:29:29Z</updated>
+  <entry>
+    <title>Cheap Monitor</title>
+    <id>urn:example:post-1</id>
+    <link rel="self" href="https://example.com/api/monitor"/>
+    <link rel="alternate" href="https://example.com/deals/monitor"/>
+    <updated>2023-03-27T06:29:29Z</updated>
+    <published>2023-03-26T06:29:29Z</published>
+    <summary>A summary</summary>
+    <content type="html">&lt;p&gt;Full content&lt;/p&gt;</content>
+    <category term="displays" label="Displays"/>
+    <category term="computing"/>
+    <media:thumbnail url="https://example.com/monitor.jpg"/>
+  </entry>
+</feed>"#;
+
+    fn parse(adapter: FeedAdapter, mapping: &FieldMapping, body: &str) -> Vec<FeedItem> {
+        parse_feed(adapter, mapping, body.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn adapters() {
+        assert_eq!("rss".parse::<FeedAdapter>().unwrap(), FeedAdapter::Rss);
+        assert_eq!("atom".parse::<FeedAdapter>().unwrap(), FeedAdapter::Atom);
+        assert_eq!(
+            "ozbargain".parse::<FeedAdapter>().unwrap(),
+            FeedAdapter::OzBargain
+        );
+        assert!("RSS".parse::<FeedAdapter>().is_err());
+    }
+
+    #[test]
+    fn rss_defaults() {
+        let items = parse(FeedAdapter::Rss, &FieldMapping::default(), RSS_FEED);
+        // items without a link are skipped
+        assert_eq!(items.len(), 2);
+
+        let item = &items[0];
+        assert_eq!(item.guid, "post-1");
+        assert_eq!(item.title, "Cheap SSD");
+        assert_eq!(item.link, "https://example.com/deals/ssd");
+        assert_eq!(item.deal_url.as_deref(), Some(item.link.as_str()));
+        assert_eq!(item.description, "Half price drives");
+        assert_eq!(
+            item.thumbnail.as_deref(),
+            Some("https://example.com/ssd-thumb.jpg")
+        );
+        assert_eq!(item.categories, ["Computing"]);
+        assert_eq!(
+            item.publication_date,
+            DateTime::parse_from_rfc3339("2023-03-26T06:29:29Z")
+                .unwrap()
+                .naive_utc()
+        );
+        assert_eq!(item.votes, None);
+    }
+
+    #[test]
+    fn rss_missing_publication_date_is_now() {
+        let before = Utc::now().naive_utc();
+        let items = parse(FeedAdapter::Rss, &FieldMapping::default(), RSS_FEED);
+
+        let item = &items[1];
+        assert_eq!(item.guid, "post-2");
+        assert_eq!(item.description, "");
+        assert!(item.publication_date >= before);
+        assert!(item.publication_date <= Utc::now().naive_utc());
+    }
+
+    #[test]
+    fn rss_field_mapping() {
+        let mapping = FieldMapping {
+            guid: Some("link".to_owned()),
+            categories: Some("dc:subject".to_owned()),
+            thumbnail: Some("enclosure".to_owned()),
+        };
+        let items = parse(FeedAdapter::Rss, &mapping, RSS_FEED);
+
+        assert_eq!(items[0].guid, "https://example.com/deals/ssd");
+        assert_eq!(items[0].categories, ["Storage"]);
+        assert_eq!(
+            items[0].thumbnail.as_deref(),
+            Some("https://example.com/ssd.jpg")
+        );
+        // only image enclosures are thumbnails
+        assert_eq!(items[1].thumbnail, None);
+        assert!(items[1].categories.is_empty());
+    }
+
+    #[test]
+    fn atom_defaults() {
+        let items = parse(FeedAdapter::Atom, &FieldMapping::default(), ATOM_FEED);
+        assert_eq!(items.len(), 1);
+
+        let item = &items[0];
+        assert_eq!(item.guid, "urn:example:post-1");
+        assert_eq!(item.title, "Cheap Monitor");
+        assert_eq!(item.link, "https://example.com/deals/monitor");
+        assert_eq!(item.description, "<p>Full content</p>");
+        assert_eq!(
+            item.thumbnail.as_deref(),
+            Some("https://example.com/monitor.jpg")
+        );
+        assert_eq!(item.categories, ["Displays", "computing"]);
+        assert_eq!(
+            item.publication_date,
+            DateTime::parse_from_rfc3339("2023-03-26T06:29:29Z")
+                .unwrap()
+                .naive_utc()
+        );
+    }
+
+    #[test]
+    fn atom_field_mapping() {
+        let mapping = FieldMapping {
+            guid: Some("link".to_owned()),
+            ..FieldMapping::default()
+        };
+        let items = parse(FeedAdapter::Atom, &mapping, ATOM_FEED);
+
+        assert_eq!(items[0].guid, "https://example.com/deals/monitor");
+    }
+
+    #[test]
+    fn invalid_feeds_are_errors() {
+        for adapter in [FeedAdapter::OzBargain, FeedAdapter::Rss, FeedAdapter::Atom] {
+            assert!(parse_feed(adapter, &FieldMapping::default(), b"not a feed").is_err());
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Circuit breaker state of a [`FetchPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Fetching normally at the regular interval
+    Closed,
+    /// Too many consecutive failures, backing off before trying again
+    Open,
+    /// Backoff elapsed, the next fetch decides whether to close or reopen
+    HalfOpen,
+}
+
+/// Decides how long to wait between fetches, backing off exponentially with
+/// jitter on consecutive failures.
+#[derive(Debug, Clone)]
+pub struct FetchPolicy {
+    pub interval: Duration,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub failure_threshold: u32,
+    failures: u32,
+    state: CircuitState,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(30 * 60),
+            failure_threshold: 3,
+            failures: 0,
+            state: CircuitState::Closed,
+        }
+    }
+}
+
+impl FetchPolicy {
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Called once the delay returned by the previous result has elapsed.
+    pub fn before_fetch(&mut self) {
+        if self.state == CircuitState::Open {
+            self.state = CircuitState::HalfOpen;
+        }
+    }
+
+    /// Records a successful fetch, returns how long to wait before the next one.
+    pub fn record_success(&mut self) -> Duration {
+        self.failures = 0;
+        self.state = CircuitState::Closed;
+
+        self.interval
+    }
+
+    /// Records a failed fetch, returns how long to wait before the next one.
+    pub fn record_failure(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        if self.state == CircuitState::HalfOpen || self.failures >= self.failure_threshold {
+            self.state = CircuitState::Open;
+        }
+
+        self.backoff_delay()
+    }
+
+    fn backoff_delay(&self) -> Duration {
+        let exponent = self.failures.saturating_sub(1).min(16);
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay);
+
+        // wait between half and all of the delay so restarted finders don't retry in lockstep
+        let half = delay / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+
+        half + Duration::from_millis(jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_at_the_failure_threshold() {
+        let mut policy = FetchPolicy::default();
+
+        for failures in 1..policy.failure_threshold {
+            policy.before_fetch();
+            policy.record_failure();
+            assert_eq!(policy.failures(), failures);
+            assert_eq!(policy.state(), CircuitState::Closed);
+        }
+
+        policy.before_fetch();
+        policy.record_failure();
+        assert_eq!(policy.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn half_open_fetch_decides() {
+        let mut policy = FetchPolicy {
+            failure_threshold: 1,
+            ..FetchPolicy::default()
+        };
+        policy.record_failure();
+        assert_eq!(policy.state(), CircuitState::Open);
+
+        policy.before_fetch();
+        assert_eq!(policy.state(), CircuitState::HalfOpen);
+        policy.record_failure();
+        assert_eq!(policy.state(), CircuitState::Open);
+
+        policy.before_fetch();
+        assert_eq!(policy.record_success(), policy.interval);
+        assert_eq!(policy.state(), CircuitState::Closed);
+        assert_eq!(policy.failures(), 0);
+
+        // a closed circuit stays closed until failures reach the threshold again
+        policy.before_fetch();
+        assert_eq!(policy.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn backoff_is_jittered_and_capped() {
+        let mut policy = FetchPolicy::default();
+
+        for failures in 1..=20 {
+            let delay = policy.record_failure();
+            let full = policy
+                .base_delay
+                .saturating_mul(2u32.saturating_pow(failures - 1))
+                .min(policy.max_delay);
+
+            assert!(delay >= full / 2, "{delay:?} < half of {full:?}");
+            assert!(delay <= full, "{delay:?} > {full:?}");
+        }
+
+        assert_eq!(policy.failures(), 20);
+    }
+}
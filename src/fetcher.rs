@@ -0,0 +1,338 @@
+use anyhow::Context;
+use reqwest::{
+    header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT},
+    StatusCode,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Settings for a [`Fetcher`], see [`FetcherConfig::from_env`].
+#[derive(Debug, Clone)]
+pub struct FetcherConfig {
+    pub user_agent: String,
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// e.g. "socks5://127.0.0.1:1080", applied to all requests
+    pub proxy: Option<String>,
+    /// Extra attempts on connection errors, 429 and 5xx responses
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after
+    pub retry_delay: Duration,
+    /// Read feeds from a file, or from a directory of captures named by [`capture_name`]
+    pub local_source: Option<PathBuf>,
+    /// Write every fetched body to this directory, named by [`capture_name`]
+    pub capture_dir: Option<PathBuf>,
+}
+
+impl Default for FetcherConfig {
+    fn default() -> Self {
+        Self {
+            // OzBargain is behind cloudflare, which blocks unknown agents
+            user_agent: "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0"
+                .to_owned(),
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            proxy: None,
+            retries: 2,
+            retry_delay: Duration::from_secs(2),
+            local_source: None,
+            capture_dir: None,
+        }
+    }
+}
+
+impl FetcherConfig {
+    /// Defaults overridden by the `FETCH_USER_AGENT`, `FETCH_TIMEOUT_SECONDS`,
+    /// `FETCH_CONNECT_TIMEOUT_SECONDS`, `FETCH_PROXY`, `FETCH_RETRIES`,
+    /// `FETCH_RETRY_DELAY_SECONDS`, `FETCH_LOCAL_SOURCE` and `FETCH_CAPTURE_DIR` env vars
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let seconds = |name: &str| -> Result<Option<Duration>, anyhow::Error> {
+            var(name)
+                .map(|v| v.parse().map(Duration::from_secs))
+                .transpose()
+                .with_context(|| format!("{name} must be a number of seconds"))
+        };
+
+        let default = Self::default();
+        Ok(Self {
+            user_agent: var("FETCH_USER_AGENT").unwrap_or(default.user_agent),
+            timeout: seconds("FETCH_TIMEOUT_SECONDS")?.unwrap_or(default.timeout),
+            connect_timeout: seconds("FETCH_CONNECT_TIMEOUT_SECONDS")?
+                .unwrap_or(default.connect_timeout),
+            proxy: var("FETCH_PROXY"),
+            retries: var("FETCH_RETRIES")
+                .map(|v| v.parse())
+                .transpose()
+                .context("FETCH_RETRIES must be a number")?
+                .unwrap_or(default.retries),
+            retry_delay: seconds("FETCH_RETRY_DELAY_SECONDS")?.unwrap_or(default.retry_delay),
+            local_source: var("FETCH_LOCAL_SOURCE").map(PathBuf::from),
+            capture_dir: var("FETCH_CAPTURE_DIR").map(PathBuf::from),
+        })
+    }
+}
+
+/// Result of a [`Fetcher::fetch`], non success statuses are returned rather than errors.
+#[derive(Debug, Clone)]
+pub struct Fetched {
+    pub status: StatusCode,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Fetches feeds over HTTP, or from local captures when configured.
+#[derive(Debug, Clone)]
+pub struct Fetcher {
+    client: reqwest::Client,
+    config: FetcherConfig,
+}
+
+impl Fetcher {
+    pub fn new(config: FetcherConfig) -> Result<Self, anyhow::Error> {
+        let mut builder = reqwest::ClientBuilder::new()
+            .cookie_store(true)
+            .timeout(config.timeout)
+            .connect_timeout(config.connect_timeout);
+
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy).context("invalid proxy")?);
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            config,
+        })
+    }
+
+    /// Client with the configured timeouts and proxy, for requests other than feeds
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Conditional GET of `url`, validators are only sent when known.
+    pub async fn fetch(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<Fetched, anyhow::Error> {
+        if let Some(local_source) = &self.config.local_source {
+            return read_local(local_source, url).await;
+        }
+
+        let mut attempt = 0;
+        let fetched = loop {
+            let result = self.fetch_once(url, etag, last_modified).await;
+            let retryable = match &result {
+                Ok(fetched) => {
+                    fetched.status == StatusCode::TOO_MANY_REQUESTS
+                        || fetched.status.is_server_error()
+                }
+                Err(e) => e
+                    .downcast_ref::<reqwest::Error>()
+                    .is_some_and(|e| e.is_connect() || e.is_timeout() || e.is_request()),
+            };
+
+            if !retryable || attempt >= self.config.retries {
+                break result?;
+            }
+
+            let delay = self.config.retry_delay * 2u32.saturating_pow(attempt);
+            attempt += 1;
+            tracing::warn!(attempt, "retrying fetch in {delay:?}");
+            tokio::time::sleep(delay).await;
+        };
+
+        if let (Some(capture_dir), true) = (&self.config.capture_dir, fetched.status.is_success()) {
+            let path = capture_dir.join(capture_name(url));
+            if let Err(e) = tokio::fs::write(&path, &fetched.body).await {
+                tracing::error!("error capturing feed to {}: {e}", path.display());
+            }
+        }
+
+        Ok(fetched)
+    }
+
+    async fn fetch_once(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<Fetched, anyhow::Error> {
+        let mut request = self
+            .client
+            .get(url)
+            .header(USER_AGENT, &self.config.user_agent);
+
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        if let Some(last_modified) = last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = request.send().await?;
+        let header = |name: HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.to_owned())
+        };
+
+        Ok(Fetched {
+            status: response.status(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+}
+
+/// File name a capture of `url` is stored as, e.g. "www.ozbargain.com.au_deals_feed"
+pub fn capture_name(url: &str) -> String {
+    let url = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+
+    url.trim_end_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+async fn read_local(local_source: &Path, url: &str) -> Result<Fetched, anyhow::Error> {
+    let path = if local_source.is_dir() {
+        local_source.join(capture_name(url))
+    } else {
+        local_source.to_owned()
+    };
+
+    let body = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("error reading local feed {}", path.display()))?;
+
+    Ok(Fetched {
+        status: StatusCode::OK,
+        etag: None,
+        last_modified: None,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::{parse_feed, FeedAdapter, FieldMapping};
+
+    const DEALS_URL: &str = "https://www.ozbargain.com.au/deals/feed";
+    const DEALS_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/" xmlns:ozb="https://www.ozbargain.com.au">
+  <channel>
+    <title>OzBargain</title>
+    <link>https://www.ozbargain.com.au/deals</link>
+    <description>Deals</description>
+    <item>
+      <title>Sony WH-1000XM5 $399 (25% off) @ JB Hi-Fi</title>
+      <link>https://www.ozbargain.com.au/node/123456</link>
+      <description><![CDATA[<p>Use code <code>SAVE20</code></p>]]></description>
+      <category domain="https://www.ozbargain.com.au/cat/electrical-electronics">Electrical &amp;amp; Electronics</category>
+      <guid isPermaLink="false">123456 at https://www.ozbargain.com.au</guid>
+      <pubDate>Sun, 26 Mar 2023 17:29:29 +1100</pubDate>
+      <ozb:meta url="https://www.jbhifi.com.au/products/sony-wh-1000xm5" votes-pos="10" votes-neg="1"/>
+      <media:thumbnail url="https://files.ozbargain.com.au/n/56/123456.jpg"/>
+    </item>
+  </channel>
+</rss>"#;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ozb-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn local_fetcher(local_source: PathBuf) -> Fetcher {
+        Fetcher::new(FetcherConfig {
+            local_source: Some(local_source),
+            ..FetcherConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn capture_names() {
+        assert_eq!(capture_name(DEALS_URL), "www.ozbargain.com.au_deals_feed");
+        assert_eq!(
+            capture_name("https://example.com/feed.xml?page=2"),
+            "example.com_feed.xml_page_2"
+        );
+        assert_eq!(capture_name("example.com/feed/"), "example.com_feed");
+    }
+
+    #[tokio::test]
+    async fn replays_captures_by_url() {
+        let dir = temp_dir("replay");
+        std::fs::write(dir.join(capture_name(DEALS_URL)), DEALS_FEED).unwrap();
+
+        let fetched = local_fetcher(dir.clone())
+            .fetch(DEALS_URL, Some("\"etag\""), None)
+            .await
+            .unwrap();
+        assert_eq!(fetched.status, StatusCode::OK);
+        assert_eq!(fetched.etag, None);
+
+        let items = parse_feed(
+            FeedAdapter::OzBargain,
+            &FieldMapping::default(),
+            &fetched.body,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 1);
+
+        let item = &items[0];
+        assert_eq!(item.guid, "123456");
+        assert_eq!(item.title, "Sony WH-1000XM5 $399 (25% off) @ JB Hi-Fi");
+        assert_eq!(item.categories, ["Electrical & Electronics"]);
+        assert_eq!(item.votes, Some((10, 1)));
+        assert_eq!(
+            item.deal_url.as_deref(),
+            Some("https://www.jbhifi.com.au/products/sony-wh-1000xm5")
+        );
+        assert_eq!(
+            item.thumbnail.as_deref(),
+            Some("https://files.ozbargain.com.au/n/56/123456.jpg")
+        );
+
+        // a source without a capture fails like a fetch would, instead of looking empty
+        assert!(local_fetcher(dir.clone())
+            .fetch("https://www.ozbargain.com.au/freebies/feed", None, None)
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replays_a_single_file_for_every_url() {
+        let dir = temp_dir("replay-file");
+        let file = dir.join("feed.xml");
+        std::fs::write(&file, DEALS_FEED).unwrap();
+
+        let fetcher = local_fetcher(file);
+        for url in [DEALS_URL, "https://example.com/other/feed"] {
+            let fetched = fetcher.fetch(url, None, None).await.unwrap();
+            assert_eq!(fetched.body, DEALS_FEED.as_bytes());
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+/// Shortest keyword allowed, shorter ones match nearly every deal
+pub const MIN_LENGTH: usize = 2;
+/// Longest keyword allowed
+pub const MAX_LENGTH: usize = 100;
+
+/// Normalises a keyword for storage and matching, with a message for the user when it's invalid.
+///
+/// Whitespace is collapsed and ASCII letters lowercased, matching only ignores the case of ASCII.
+pub fn normalize_keyword(keyword: &str) -> Result<String, anyhow::Error> {
+    let keyword = keyword
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_ascii_lowercase();
+
+    let length = keyword.chars().count();
+    if length < MIN_LENGTH {
+        anyhow::bail!("Keywords must be at least {MIN_LENGTH} characters");
+    }
+
+    if length > MAX_LENGTH {
+        anyhow::bail!("Keywords must be at most {MAX_LENGTH} characters");
+    }
+
+    if !keyword.chars().any(char::is_alphanumeric) {
+        anyhow::bail!("Keywords must contain a letter or number");
+    }
+
+    if keyword.chars().any(char::is_control) {
+        anyhow::bail!("Keywords can't contain control characters");
+    }
+
+    Ok(keyword)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_whitespace_and_lowercases_ascii() {
+        assert_eq!(normalize_keyword("  NVMe \t  SSD\n").unwrap(), "nvme ssd");
+        // "über" wouldn't match "Über" in a title, so non-ASCII letters keep their case
+        assert_eq!(normalize_keyword("Über EATS").unwrap(), "Über eats");
+    }
+
+    #[test]
+    fn length_bounds() {
+        assert!(normalize_keyword("a").is_err());
+        assert!(normalize_keyword(" a  ").is_err());
+        assert_eq!(normalize_keyword("4k").unwrap(), "4k");
+        assert!(normalize_keyword(&"é".repeat(MAX_LENGTH)).is_ok());
+        assert!(normalize_keyword(&"a".repeat(MAX_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_keywords() {
+        assert!(normalize_keyword("!!!").is_err());
+        assert!(normalize_keyword("$ %").is_err());
+        assert!(normalize_keyword("ssd\u{7}").is_err());
+    }
+}
@@ -1,3 +1,9 @@
+pub mod feed;
+pub mod fetch_policy;
+pub mod fetcher;
+pub mod keyword;
+pub mod matcher;
+pub mod notification;
 pub mod ozbargain;
 pub mod util;
 
@@ -0,0 +1,122 @@
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use std::collections::HashMap;
+
+/// Finds every registered keyword contained in a post in one pass over its text.
+///
+/// Matching is ASCII case insensitive, same as comparing lowercased strings.
+#[derive(Debug, Clone)]
+pub struct KeywordMatcher {
+    automaton: AhoCorasick,
+    /// registrations for each pattern, registrations with the same keyword share a pattern
+    registrations: Vec<Vec<usize>>,
+    /// empty keywords are contained in everything
+    always: Vec<usize>,
+    len: usize,
+}
+
+impl KeywordMatcher {
+    /// Builds the matcher, registrations are identified by their index in `keywords`.
+    pub fn new<'a>(keywords: impl IntoIterator<Item = &'a str>) -> Result<Self, anyhow::Error> {
+        let mut patterns = Vec::<String>::new();
+        let mut pattern_ids = HashMap::<String, usize>::new();
+        let mut registrations = Vec::<Vec<usize>>::new();
+        let mut always = Vec::new();
+        let mut len = 0;
+
+        for (index, keyword) in keywords.into_iter().enumerate() {
+            len += 1;
+            let keyword = keyword.to_ascii_lowercase();
+            if keyword.is_empty() {
+                always.push(index);
+                continue;
+            }
+
+            match pattern_ids.get(&keyword) {
+                Some(&pattern) => registrations[pattern].push(index),
+                None => {
+                    pattern_ids.insert(keyword.clone(), patterns.len());
+                    patterns.push(keyword);
+                    registrations.push(vec![index]);
+                }
+            }
+        }
+
+        let automaton = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(true)
+            .match_kind(MatchKind::Standard)
+            .build(&patterns)?;
+
+        Ok(Self {
+            automaton,
+            registrations,
+            always,
+            len,
+        })
+    }
+
+    /// Number of registrations the matcher was built with
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Indices in `keywords` of the registrations whose keyword is in any of the texts, ascending
+    pub fn matches(&self, texts: &[&str]) -> Vec<usize> {
+        // overlapping so "ssd" still matches inside "nvme ssd"
+        let mut patterns = texts
+            .iter()
+            .flat_map(|text| self.automaton.find_overlapping_iter(*text))
+            .map(|found| found.pattern().as_usize())
+            .collect::<Vec<_>>();
+        patterns.sort_unstable();
+        patterns.dedup();
+
+        // each registration belongs to one pattern, so there are no duplicates
+        let mut matched = self.always.clone();
+        matched.extend(
+            patterns
+                .into_iter()
+                .flat_map(|pattern| self.registrations[pattern].iter().copied()),
+        );
+        matched.sort_unstable();
+
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_registration_indices() {
+        let matcher = KeywordMatcher::new(["ssd", "lego", "NVMe SSD", "ssd", "coffee"]).unwrap();
+
+        assert_eq!(matcher.len(), 5);
+        assert_eq!(
+            matcher.matches(&["Samsung 990 Pro NVME SSD", "ssd ssd ssd"]),
+            [0, 2, 3]
+        );
+        assert_eq!(matcher.matches(&["", "Lego Technic"]), [1]);
+        assert!(matcher.matches(&["nothing here"]).is_empty());
+    }
+
+    #[test]
+    fn empty_keywords_match_everything() {
+        let matcher = KeywordMatcher::new(["", "lego"]).unwrap();
+
+        assert_eq!(matcher.matches(&["anything"]), [0]);
+        assert_eq!(matcher.matches(&["LEGO"]), [0, 1]);
+    }
+
+    #[test]
+    fn only_ascii_is_case_insensitive() {
+        let matcher = KeywordMatcher::new(["über", "cafe"]).unwrap();
+
+        assert_eq!(matcher.matches(&["Über Eats"]), Vec::<usize>::new());
+        assert_eq!(matcher.matches(&["über CAFE"]), [0, 1]);
+    }
+}
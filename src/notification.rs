@@ -0,0 +1,569 @@
+use crate::ozbargain;
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use std::str::FromStr;
+use twilight_model::channel::message::{
+    embed::{EmbedField, EmbedFooter, EmbedImage, EmbedThumbnail},
+    Embed,
+};
+
+/// Actions attached as buttons to a notification, encoded in the button custom_id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationAction {
+    /// Remove the registered keyword that matched.
+    Unregister { keyword_id: i32 },
+    /// Stop notifying for the registered keyword for 24 hours.
+    Snooze { keyword_id: i32 },
+    /// Stop notifying the user for deals from the merchant of this post.
+    MuteMerchant { post_id: i32 },
+}
+
+impl NotificationAction {
+    const UNREGISTER: &'static str = "ozb-unregister";
+    const SNOOZE: &'static str = "ozb-snooze";
+    const MUTE_MERCHANT: &'static str = "ozb-mute-merchant";
+
+    pub fn custom_id(&self) -> String {
+        match self {
+            Self::Unregister { keyword_id } => format!("{}:{}", Self::UNREGISTER, keyword_id),
+            Self::Snooze { keyword_id } => format!("{}:{}", Self::SNOOZE, keyword_id),
+            Self::MuteMerchant { post_id } => format!("{}:{}", Self::MUTE_MERCHANT, post_id),
+        }
+    }
+
+    pub fn parse(custom_id: &str) -> Option<Self> {
+        let (action, id) = custom_id.split_once(':')?;
+        let id = id.parse().ok()?;
+
+        match action {
+            Self::UNREGISTER => Some(Self::Unregister { keyword_id: id }),
+            Self::SNOOZE => Some(Self::Snooze { keyword_id: id }),
+            Self::MUTE_MERCHANT => Some(Self::MuteMerchant { post_id: id }),
+            _ => None,
+        }
+    }
+}
+
+/// Discord embed limits, see https://discord.com/developers/docs/resources/message#embed-object-embed-limits
+pub mod limits {
+    pub const TITLE: usize = 256;
+    pub const DESCRIPTION: usize = 4096;
+    pub const FIELD_NAME: usize = 256;
+    pub const FIELD_VALUE: usize = 1024;
+    pub const FOOTER: usize = 2048;
+    pub const TOTAL: usize = 6000;
+    pub const CONTENT: usize = 2000;
+}
+
+const DESCRIPTION_SNIPPET_LENGTH: usize = 300;
+const COMPACT_TEMPLATE: &str = "**{title}** - \"{keyword}\" <{link}>";
+pub const TEMPLATE_PLACEHOLDERS: [&str; 8] = [
+    "title",
+    "price",
+    "discount",
+    "merchant",
+    "codes",
+    "keyword",
+    "link",
+    "categories",
+];
+const EMBED_COLOR: u32 = 0xde935f;
+
+/// Layout of the notification sent for a matched deal.
+#[derive(Debug, Clone)]
+pub struct DealNotification {
+    pub title: String,
+    pub link: String,
+    /// Plain text description, see [`crate::ozbargain::description_text`]
+    pub description: String,
+    pub keyword: String,
+    pub categories: Vec<String>,
+    pub thumbnail: Option<String>,
+    pub publication_date: NaiveDateTime,
+    pub ozbargain_id: String,
+    pub votes: Option<(i32, i32)>,
+    pub coupon_codes: Vec<String>,
+    /// Why the deal is notified again, e.g. a price drop, shown after the mention
+    pub note: Option<String>,
+}
+
+/// How a registration wants its notifications laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NotificationFormat {
+    /// Single line message, no embed
+    Compact,
+    #[default]
+    Standard,
+    /// Standard embed with the full description and a large image
+    Detailed,
+    /// User provided text template, see [`validate_template`]
+    Custom,
+}
+
+impl NotificationFormat {
+    pub const ALL: [Self; 4] = [Self::Compact, Self::Standard, Self::Detailed, Self::Custom];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Compact => "compact",
+            Self::Standard => "standard",
+            Self::Detailed => "detailed",
+            Self::Custom => "custom",
+        }
+    }
+}
+
+impl FromStr for NotificationFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.as_str() == s)
+            .with_context(|| format!("unknown notification format: {s}"))
+    }
+}
+
+/// Message content and embed to send for a notification.
+#[derive(Debug, Clone)]
+pub struct RenderedNotification {
+    pub content: String,
+    pub embed: Option<Embed>,
+}
+
+impl DealNotification {
+    /// Renders the notification, `mention` is prefixed to the message content.
+    pub fn render(
+        &self,
+        mention: &str,
+        format: NotificationFormat,
+        template: Option<&str>,
+    ) -> Result<RenderedNotification, anyhow::Error> {
+        let (text, embed) = match format {
+            NotificationFormat::Compact => (Some(self.render_template(COMPACT_TEMPLATE)?), None),
+            NotificationFormat::Standard => (None, Some(self.embed())),
+            NotificationFormat::Detailed => (None, Some(self.detailed_embed())),
+            NotificationFormat::Custom => {
+                let template = template.context("custom format requires a template")?;
+                (Some(self.render_template(template)?), None)
+            }
+        };
+
+        let mention = match &self.note {
+            Some(note) => format!("{mention} {note}"),
+            None => mention.to_owned(),
+        };
+
+        let content = match text {
+            Some(text) => truncate(&format!("{mention} {text}"), limits::CONTENT),
+            None => truncate(&mention, limits::CONTENT),
+        };
+
+        Ok(RenderedNotification { content, embed })
+    }
+
+    /// Replaces `{placeholder}`s in the template, see [`TEMPLATE_PLACEHOLDERS`]
+    pub fn render_template(&self, template: &str) -> Result<String, anyhow::Error> {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .with_context(|| format!("unclosed placeholder at: {}", &rest[start..]))?;
+
+            let value = match &rest[start + 1..start + end] {
+                "title" => self.title.clone(),
+                "price" => ozbargain::price(&self.title).unwrap_or_default().to_owned(),
+                "discount" => ozbargain::discount(&self.title)
+                    .unwrap_or_default()
+                    .to_owned(),
+                "merchant" => ozbargain::merchant(&self.title)
+                    .unwrap_or_default()
+                    .to_owned(),
+                "codes" => self.coupon_codes.join(", "),
+                "keyword" => self.keyword.clone(),
+                "link" => self.link.clone(),
+                "categories" => self.categories.join(", "),
+                unknown => anyhow::bail!(
+                    "unknown placeholder {{{unknown}}}, expected one of: {}",
+                    TEMPLATE_PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+                ),
+            };
+
+            rendered.push_str(&value);
+            rest = &rest[start + end + 1..];
+        }
+
+        rendered.push_str(rest);
+
+        Ok(rendered)
+    }
+
+    /// Sample deal used to check templates before they are saved
+    pub fn sample() -> Self {
+        Self {
+            title: "Sony WH-1000XM5 Noise Cancelling Headphones $399 (25% off) @ JB Hi-Fi"
+                .to_owned(),
+            link: "https://www.ozbargain.com.au/node/123456".to_owned(),
+            description: "Great price on these headphones.".to_owned(),
+            keyword: "headphones".to_owned(),
+            categories: vec!["Electrical & Electronics".to_owned()],
+            thumbnail: None,
+            publication_date: NaiveDateTime::default(),
+            ozbargain_id: "123456".to_owned(),
+            votes: Some((10, 0)),
+            coupon_codes: vec!["SAVE20".to_owned()],
+            note: None,
+        }
+    }
+
+    pub fn embed(&self) -> Embed {
+        self.build_embed(DESCRIPTION_SNIPPET_LENGTH)
+    }
+
+    pub fn detailed_embed(&self) -> Embed {
+        let mut embed = self.build_embed(limits::DESCRIPTION);
+        embed.image = embed.thumbnail.take().map(|thumbnail| EmbedImage {
+            height: None,
+            proxy_url: None,
+            url: thumbnail.url,
+            width: None,
+        });
+
+        embed
+    }
+
+    fn build_embed(&self, description_length: usize) -> Embed {
+        let mut fields = Vec::new();
+        let mut field = |name: &str, value: String, inline: bool| {
+            fields.push(EmbedField {
+                inline,
+                name: truncate(name, limits::FIELD_NAME),
+                value: truncate(&value, limits::FIELD_VALUE),
+            })
+        };
+
+        if let Some(price) = ozbargain::price(&self.title) {
+            let price = match ozbargain::discount(&self.title) {
+                Some(discount) => format!("{price} ({discount})"),
+                None => price.to_owned(),
+            };
+
+            field("Price", price, true);
+        } else if let Some(discount) = ozbargain::discount(&self.title) {
+            field("Discount", discount.to_owned(), true);
+        }
+
+        if let Some(merchant) = ozbargain::merchant(&self.title) {
+            field("Merchant", merchant.to_owned(), true);
+        }
+
+        if let Some((positive, negative)) = self.votes {
+            field("Votes", format!("+{positive} / -{negative}"), true);
+        }
+
+        if !self.coupon_codes.is_empty() {
+            // inline code is easy to copy on mobile
+            let name = if self.coupon_codes.len() == 1 {
+                "Coupon code"
+            } else {
+                "Coupon codes"
+            };
+            let codes = self
+                .coupon_codes
+                .iter()
+                .map(|code| format!("`{code}`"))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            field(name, codes, true);
+        }
+
+        field("Keyword", self.keyword.clone(), true);
+        if !self.categories.is_empty() {
+            field("Categories", self.categories.join(", "), true);
+        }
+
+        field(
+            "Posted",
+            format!("<t:{}:R>", self.publication_date.and_utc().timestamp()),
+            true,
+        );
+
+        let title = truncate(&self.title, limits::TITLE);
+        // guids of other feeds can be long urls, keep room for the description
+        let footer = truncate(&format!("Deal {}", self.ozbargain_id), limits::TITLE);
+        let used = title.chars().count()
+            + footer.chars().count()
+            + fields
+                .iter()
+                .map(|f| f.name.chars().count() + f.value.chars().count())
+                .sum::<usize>();
+
+        let description = truncate(
+            &self.description,
+            description_length
+                .min(limits::DESCRIPTION)
+                .min(limits::TOTAL.saturating_sub(used)),
+        );
+
+        Embed {
+            author: None,
+            color: Some(EMBED_COLOR),
+            description: (!description.is_empty()).then_some(description),
+            fields,
+            footer: Some(EmbedFooter {
+                icon_url: None,
+                proxy_icon_url: None,
+                text: footer,
+            }),
+            image: None,
+            kind: "rich".to_owned(),
+            provider: None,
+            thumbnail: self.thumbnail.as_ref().map(|url| EmbedThumbnail {
+                height: None,
+                proxy_url: None,
+                url: url.to_owned(),
+                width: None,
+            }),
+            timestamp: None,
+            title: Some(title),
+            url: Some(self.link.clone()),
+            video: None,
+        }
+    }
+}
+
+/// Checks a custom template renders and fits in a message
+pub fn validate_template(template: &str) -> Result<(), anyhow::Error> {
+    let rendered = DealNotification::sample().render_template(template)?;
+    if rendered.trim().is_empty() {
+        anyhow::bail!("template must not be empty");
+    }
+
+    // leave room for the mention and longer titles than the sample
+    let max = limits::CONTENT - 500;
+    if rendered.chars().count() > max {
+        anyhow::bail!("template is too long, it must render to at most {max} characters");
+    }
+
+    Ok(())
+}
+
+/// Total characters counted towards [`limits::TOTAL`]
+pub fn embed_length(embed: &Embed) -> usize {
+    let count = |s: &Option<String>| s.as_ref().map(|s| s.chars().count()).unwrap_or(0);
+
+    count(&embed.title)
+        + count(&embed.description)
+        + embed
+            .footer
+            .as_ref()
+            .map(|f| f.text.chars().count())
+            .unwrap_or(0)
+        + embed
+            .author
+            .as_ref()
+            .map(|a| a.name.chars().count())
+            .unwrap_or(0)
+        + embed
+            .fields
+            .iter()
+            .map(|f| f.name.chars().count() + f.value.chars().count())
+            .sum::<usize>()
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    if value.chars().count() <= max {
+        return value.to_owned();
+    }
+
+    if max == 0 {
+        return String::new();
+    }
+
+    let mut truncated = value.chars().take(max - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn long_notification() -> DealNotification {
+        DealNotification {
+            title: format!(
+                "{} $1,299.95 (40% off) @ {}",
+                "Ünïcödé 🔥 ".repeat(60),
+                "Über Stöře 🛒".repeat(200)
+            ),
+            link: "https://www.ozbargain.com.au/node/123456".to_owned(),
+            description: "Lörem ipsüm 😀 dolor sit amet. ".repeat(500),
+            keyword: "k".repeat(1500),
+            categories: (0..100).map(|i| format!("Catégorie {i} 🎮")).collect(),
+            thumbnail: Some("https://files.ozbargain.com.au/n/56/123456.jpg".to_owned()),
+            publication_date: NaiveDateTime::default(),
+            ozbargain_id: "https://example.com/a/very/long/guid/".repeat(100),
+            votes: Some((i32::MAX, i32::MAX)),
+            coupon_codes: (0..300).map(|i| format!("SAVE{i}NOW")).collect(),
+            note: Some("Price dropped from $1,499".to_owned()),
+        }
+    }
+
+    fn assert_within_limits(embed: &Embed) {
+        let count = |s: &str| s.chars().count();
+
+        assert!(embed_length(embed) <= limits::TOTAL);
+        assert!(count(embed.title.as_deref().unwrap_or_default()) <= limits::TITLE);
+        assert!(count(embed.description.as_deref().unwrap_or_default()) <= limits::DESCRIPTION);
+        assert!(embed
+            .footer
+            .as_ref()
+            .is_none_or(|f| count(&f.text) <= limits::FOOTER));
+        assert!(embed.fields.len() <= 25);
+        for field in &embed.fields {
+            assert!(count(&field.name) <= limits::FIELD_NAME);
+            assert!(count(&field.value) <= limits::FIELD_VALUE);
+        }
+    }
+
+    #[test]
+    fn embeds_fit_discord_limits() {
+        let notification = long_notification();
+
+        assert_within_limits(&notification.embed());
+        assert_within_limits(&notification.detailed_embed());
+        assert_within_limits(&DealNotification::sample().embed());
+        assert_within_limits(&DealNotification::sample().detailed_embed());
+    }
+
+    #[test]
+    fn long_values_are_truncated_not_dropped() {
+        let embed = long_notification().detailed_embed();
+
+        assert!(embed.title.unwrap().ends_with('…'));
+        assert!(embed.description.is_some());
+        for name in [
+            "Price",
+            "Merchant",
+            "Votes",
+            "Coupon codes",
+            "Keyword",
+            "Categories",
+        ] {
+            assert!(
+                embed.fields.iter().any(|f| f.name == name),
+                "missing {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn content_fits_discord_limits() {
+        let notification = long_notification();
+        for format in [NotificationFormat::Compact, NotificationFormat::Standard] {
+            let rendered = notification.render("<@1234>", format, None).unwrap();
+            assert!(rendered.content.chars().count() <= limits::CONTENT);
+        }
+    }
+
+    #[test]
+    fn standard_embed_fields() {
+        let embed = DealNotification::sample().embed();
+        let field = |name: &str| {
+            embed
+                .fields
+                .iter()
+                .find(|f| f.name == name)
+                .map(|f| f.value.as_str())
+        };
+
+        assert_eq!(field("Price"), Some("$399 (25% off)"));
+        assert_eq!(field("Merchant"), Some("JB Hi-Fi"));
+        assert_eq!(field("Votes"), Some("+10 / -0"));
+        assert_eq!(field("Coupon code"), Some("`SAVE20`"));
+        assert_eq!(embed.footer.unwrap().text, "Deal 123456");
+    }
+
+    #[test]
+    fn truncate_keeps_whole_characters() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("Über 🔥🔥🔥", 6), "Über …");
+        assert_eq!(truncate("anything", 0), "");
+    }
+
+    #[test]
+    fn renders_every_placeholder() {
+        let sample = DealNotification::sample();
+
+        assert_eq!(
+            sample
+                .render_template("{title}|{price}|{discount}|{merchant}|{codes}|{keyword}|{link}|{categories}")
+                .unwrap(),
+            "Sony WH-1000XM5 Noise Cancelling Headphones $399 (25% off) @ JB Hi-Fi|$399|25% off|JB Hi-Fi|SAVE20|headphones|https://www.ozbargain.com.au/node/123456|Electrical & Electronics"
+        );
+        assert_eq!(
+            sample.render_template("no placeholders").unwrap(),
+            "no placeholders"
+        );
+        assert_eq!(
+            sample.render_template("Ünï {keyword} 🔥").unwrap(),
+            "Ünï headphones 🔥"
+        );
+    }
+
+    #[test]
+    fn missing_values_render_empty() {
+        let notification = DealNotification {
+            title: "Free Stuff".to_owned(),
+            coupon_codes: Vec::new(),
+            categories: Vec::new(),
+            ..DealNotification::sample()
+        };
+
+        assert_eq!(
+            notification
+                .render_template("[{price}][{discount}][{merchant}][{codes}][{categories}]")
+                .unwrap(),
+            "[][][][][]"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(validate_template("{title} {link}").is_ok());
+        assert!(validate_template("{unknown}").is_err());
+        assert!(validate_template("{title").is_err());
+        assert!(validate_template("   ").is_err());
+        assert!(validate_template(&"{title}".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn custom_format_needs_a_template() {
+        let sample = DealNotification::sample();
+
+        assert!(sample
+            .render("<@1>", NotificationFormat::Custom, None)
+            .is_err());
+        let rendered = sample
+            .render("<@1>", NotificationFormat::Custom, Some("{keyword}"))
+            .unwrap();
+        assert_eq!(rendered.content, "<@1> headphones");
+        assert!(rendered.embed.is_none());
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        for format in NotificationFormat::ALL {
+            assert_eq!(
+                format.as_str().parse::<NotificationFormat>().unwrap(),
+                format
+            );
+        }
+
+        assert!("fancy".parse::<NotificationFormat>().is_err());
+    }
+}
@@ -1 +1,290 @@
-pub const OZB_RSS_DEALS_URL: &str = "https://www.ozbargain.com.au/deals/feed";
+/// Name of the default feed source, see the `feed_sources` table
+pub const DEALS_SOURCE: &str = "deals";
+
+/// Deal titles are suffixed with the merchant, e.g. "Some Product $10 @ Merchant"
+pub fn merchant(title: &str) -> Option<&str> {
+    title
+        .rsplit_once(" @ ")
+        .map(|(_, merchant)| merchant.trim())
+        .filter(|merchant| !merchant.is_empty())
+}
+
+/// First dollar amount in the title, e.g. "$1,299.95"
+pub fn price(title: &str) -> Option<&str> {
+    let start = title.find('$')?;
+    let amount = &title[start + 1..];
+    let end = amount
+        .find(|c: char| !(c.is_ascii_digit() || c == ',' || c == '.'))
+        .unwrap_or(amount.len());
+    let amount = amount[..end].trim_end_matches(['.', ',']);
+
+    if amount.starts_with(|c: char| c.is_ascii_digit()) {
+        Some(&title[start..start + 1 + amount.len()])
+    } else {
+        None
+    }
+}
+
+/// Numeric value of [`price`], for comparing prices across revisions
+pub fn price_value(title: &str) -> Option<f64> {
+    price(title)?[1..].replace(',', "").parse().ok()
+}
+
+/// Formats a [`price_value`] the way titles do, e.g. "$1,299.95" or "$10"
+pub fn format_price(value: f64) -> String {
+    let cents = (value * 100.0).round() as u64;
+    let dollars = (cents / 100).to_string();
+
+    let mut formatted = String::from("$");
+    for (i, digit) in dollars.chars().enumerate() {
+        if i > 0 && (dollars.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+
+    if !cents.is_multiple_of(100) {
+        formatted.push_str(&format!(".{:02}", cents % 100));
+    }
+
+    formatted
+}
+
+/// Whether the url is only a store's homepage, as store-wide deals link to,
+/// which doesn't tell deals of that store apart
+pub fn is_homepage(url: &str) -> bool {
+    let url = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+
+    url.split_once('/')
+        .is_none_or(|(_, path)| path.trim_matches('/').is_empty())
+}
+
+/// Title without prices and discounts, lowercased, so re-posts of the same
+/// product at the same merchant share a key
+pub fn product_key(title: &str) -> String {
+    let mut title = title.to_lowercase();
+    while let Some(price) = price(&title).map(str::to_owned) {
+        title = title.replacen(&price, " ", 1);
+    }
+    while let Some(discount) = discount(&title).map(str::to_owned) {
+        title = title.replacen(&discount, " ", 1);
+    }
+
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Percentage discount in the title, e.g. "25% off"
+pub fn discount(title: &str) -> Option<&str> {
+    let percent = title.find('%')?;
+    let start = title[..percent]
+        .char_indices()
+        .rfind(|(_, c)| !c.is_ascii_digit())
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(0);
+
+    if start == percent {
+        return None;
+    }
+
+    let end = if title[percent + 1..]
+        .to_ascii_lowercase()
+        .starts_with(" off")
+    {
+        percent + 5
+    } else {
+        percent + 1
+    };
+
+    Some(&title[start..end])
+}
+
+/// Coupon codes in `<code>` or bold text of a post description, e.g. "SAVE20"
+pub fn coupon_codes(description: &str) -> Vec<String> {
+    let Ok(dom) = tl::parse(description, tl::ParserOptions::default()) else {
+        return Vec::new();
+    };
+
+    let mut codes = Vec::new();
+    for node in dom.nodes() {
+        let Some(tag) = node.as_tag() else {
+            continue;
+        };
+
+        // bold text is mostly emphasis, e.g. "UPDATE", only take shouty tokens with digits
+        let bold = match tag.name().as_utf8_str().to_ascii_lowercase().as_str() {
+            "code" => false,
+            "b" | "strong" => true,
+            _ => continue,
+        };
+
+        let text = tag.inner_text(dom.parser());
+        for token in text.split_whitespace() {
+            let token = token.trim_matches(|c: char| !c.is_alphanumeric());
+            let looks_like_code = (4..=32).contains(&token.len())
+                && token
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                && token.chars().any(|c| c.is_ascii_alphabetic())
+                && (!bold
+                    || (token.chars().any(|c| c.is_ascii_digit())
+                        && !token.chars().any(|c| c.is_ascii_lowercase())));
+
+            if looks_like_code && !codes.iter().any(|c| c == token) {
+                codes.push(token.to_owned());
+            }
+        }
+    }
+
+    codes
+}
+
+/// Plain text of a post description, including image alt text
+pub fn description_text(description: &str) -> String {
+    tl::parse(description, tl::ParserOptions::default())
+        .map(|dom| {
+            let mut string_list = Vec::new();
+            for node in dom.nodes() {
+                match node {
+                    tl::Node::Tag(tag) if tag.name() == "img" => {
+                        if let Some(Some(alt)) = tag.attributes().get("alt") {
+                            string_list.push(alt.as_utf8_str().to_string());
+                        }
+                    }
+                    tl::Node::Raw(text) => string_list.push(text.as_utf8_str().to_string()),
+                    _ => {}
+                }
+            }
+
+            string_list
+                .iter()
+                .flat_map(|s| s.split_whitespace())
+                .collect::<Vec<_>>()
+                .join(" ")
+                .replace("&nbsp;", " ")
+                .replace("&quot;", "\"")
+                .replace("&#039;", "'")
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&amp;", "&")
+        })
+        .unwrap_or(description.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prices() {
+        assert_eq!(price("Headphones $399 @ JB Hi-Fi"), Some("$399"));
+        assert_eq!(price("Laptop $1,299.95 (Was $1,599)"), Some("$1,299.95"));
+        assert_eq!(price("Ends in $5."), Some("$5"));
+        assert_eq!(price("Free Shipping, No $ Needed"), None);
+        assert_eq!(price("Über Gutschein €10"), None);
+
+        assert_eq!(price_value("Laptop $1,299.95 (Was $1,599)"), Some(1299.95));
+        assert_eq!(price_value("No price"), None);
+    }
+
+    #[test]
+    fn discounts() {
+        assert_eq!(discount("Headphones $399 (25% off) @ JB"), Some("25% off"));
+        assert_eq!(discount("Up to 50% Off Storewide"), Some("50% Off"));
+        assert_eq!(discount("Cashback 10%"), Some("10%"));
+        assert_eq!(discount("100% Free"), Some("100%"));
+        assert_eq!(discount("Save % on stuff"), None);
+        assert_eq!(discount("No discount"), None);
+        // the character before the digits can be multi-byte
+        assert_eq!(discount("Deal –30% @ X"), Some("30%"));
+        assert_eq!(discount("Deal —20% off @ Shop"), Some("20% off"));
+        assert_eq!(discount("Rabatt ü%"), None);
+        assert_eq!(product_key("Deal –30% @ X"), "deal x");
+    }
+
+    #[test]
+    fn merchants() {
+        assert_eq!(merchant("Headphones $399 @ JB Hi-Fi"), Some("JB Hi-Fi"));
+        assert_eq!(merchant("Email me @ home"), Some("home"));
+        assert_eq!(merchant("No merchant"), None);
+        assert_eq!(merchant("Trailing @ "), None);
+    }
+
+    #[test]
+    fn formats_prices() {
+        assert_eq!(format_price(10.0), "$10");
+        assert_eq!(format_price(1299.95), "$1,299.95");
+        assert_eq!(format_price(1234567.5), "$1,234,567.50");
+        assert_eq!(format_price(0.99), "$0.99");
+        assert_eq!(
+            format_price(price_value("Laptop $1,299.95").unwrap()),
+            "$1,299.95"
+        );
+    }
+
+    #[test]
+    fn product_keys_ignore_price_and_discount() {
+        assert_eq!(
+            product_key("Sony WH-1000XM5 $399 (25% off) @ JB Hi-Fi"),
+            "sony wh 1000xm5 jb hi fi"
+        );
+        assert_eq!(
+            product_key("Sony WH-1000XM5 $349 (30% Off) @ JB Hi-Fi"),
+            product_key("Sony WH-1000XM5 $399 @ JB Hi-Fi")
+        );
+        assert_eq!(
+            product_key("Über Gutschein 🔥 @ Müller"),
+            "über gutschein müller"
+        );
+        assert_ne!(
+            product_key("Sony WH-1000XM5 $399 @ JB Hi-Fi"),
+            product_key("Sony WH-1000XM4 $399 @ JB Hi-Fi")
+        );
+    }
+
+    #[test]
+    fn homepages() {
+        assert!(is_homepage("https://www.jbhifi.com.au"));
+        assert!(is_homepage("https://www.jbhifi.com.au/"));
+        assert!(is_homepage(
+            "https://www.jbhifi.com.au/?utm_source=ozbargain"
+        ));
+        assert!(is_homepage("https://www.jbhifi.com.au#deals"));
+        assert!(!is_homepage(
+            "https://www.jbhifi.com.au/products/sony-wh-1000xm5"
+        ));
+        assert!(!is_homepage("https://www.jbhifi.com.au/sale/?page=2"));
+    }
+
+    #[test]
+    fn coupon_codes_from_code_and_bold() {
+        assert_eq!(
+            coupon_codes(
+                "<p>Use code <code>save20</code> or <strong>EXTRA10</strong> at checkout</p>"
+            ),
+            ["save20", "EXTRA10"]
+        );
+        assert_eq!(
+            coupon_codes("<code>SAVE20</code> <b>SAVE20</b> <code>SAVE-20_X</code>"),
+            ["SAVE20", "SAVE-20_X"]
+        );
+        assert_eq!(
+            coupon_codes("<p>Code: <code>(OZB2024).</code></p>"),
+            ["OZB2024"]
+        );
+        assert_eq!(coupon_codes("<b>“SAVE20”</b>"), ["SAVE20"]);
+    }
+
+    #[test]
+    fn coupon_codes_skip_emphasis() {
+        assert!(coupon_codes("<b>UPDATE</b> <strong>FREE SHIPPING</strong>").is_empty());
+        assert!(coupon_codes("<b>Ends 31/12</b> <strong>Save20 today</strong>").is_empty());
+        assert!(coupon_codes("<code>123456</code> <code>AB</code>").is_empty());
+        assert!(coupon_codes("<code>Über10</code>").is_empty());
+        assert!(coupon_codes("no markup SAVE20").is_empty());
+    }
+}